@@ -0,0 +1,275 @@
+#![allow(dead_code)]
+use std::io::{self, Read, Write};
+use my_math::prelude::*;
+use crate::quadtree::*;
+
+// a grayscale image as read from a netpbm file, 0 is black and max is white
+// pbm images are read with a max of 1
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub max: u16,
+    pub pixels: Vec<u16>,
+}
+impl Image {
+    // the pixels darker than the threshold are solid
+    pub fn threshold(&self, threshold: u16) -> Vec<bool> {
+        self.pixels.iter().map(|pixel| *pixel < threshold).collect()
+    }
+    // solid below half of the max, for a pbm that is the black pixels
+    pub fn to_bitmap(&self) -> Vec<bool> {
+        self.threshold(self.max.div_ceil(2))
+    }
+}
+
+fn corrupt(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct Parser {
+    data: Vec<u8>,
+    pos: usize,
+}
+impl Parser {
+    fn skip_space(&mut self) {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => {
+                    while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+    fn number(&mut self) -> io::Result<usize> {
+        self.skip_space();
+        let start = self.pos;
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            if self.pos == self.data.len() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            return Err(corrupt("expected a number"));
+        }
+        std::str::from_utf8(&self.data[start..self.pos]).unwrap()
+            .parse()
+            .map_err(|_| corrupt("number too large"))
+    }
+    // the single whitespace between the header and the binary data
+    fn end_header(&mut self) -> io::Result<()> {
+        if self.pos >= self.data.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+    fn byte(&mut self) -> io::Result<u8> {
+        if self.pos >= self.data.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += 1;
+        Ok(self.data[self.pos - 1])
+    }
+}
+
+// reads a P1, P2, P4 or P5 image
+pub fn read_netpbm(r: &mut impl Read) -> io::Result<Image> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let mut p = Parser { data, pos: 0 };
+
+    if p.byte()? != b'P' {
+        return Err(corrupt("not a netpbm image"));
+    }
+    let kind = p.byte()?;
+    if !matches!(kind, b'1' | b'2' | b'4' | b'5') {
+        return Err(corrupt("only pbm and pgm images are supported"));
+    }
+    let width = p.number()?;
+    let height = p.number()?;
+    let max = if kind == b'1' || kind == b'4' {
+        1
+    } else {
+        p.number()?
+    };
+    if max == 0 || max > u16::MAX as usize {
+        return Err(corrupt("invalid max gray value"));
+    }
+    let len = width.checked_mul(height).ok_or_else(|| corrupt("image too large"))?;
+    if len > p.data.len() * 8 {
+        // even a packed pbm needs a bit per pixel
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let mut pixels = Vec::with_capacity(len);
+    match kind {
+        b'1' => {
+            // 1 is black in a pbm
+            for _ in 0..len {
+                p.skip_space();
+                match p.byte()? {
+                    b'0' => pixels.push(1),
+                    b'1' => pixels.push(0),
+                    _ => return Err(corrupt("pbm pixels must be 0 or 1")),
+                }
+            }
+        }
+        b'2' => {
+            for _ in 0..len {
+                let pixel = p.number()?;
+                if pixel > max {
+                    return Err(corrupt("pixel brighter than the max gray value"));
+                }
+                pixels.push(pixel as u16);
+            }
+        }
+        b'4' => {
+            p.end_header()?;
+            // rows are padded to whole bytes, the highest bit is the leftmost pixel
+            for _ in 0..height {
+                let mut byte = 0;
+                for x in 0..width {
+                    if x % 8 == 0 {
+                        byte = p.byte()?;
+                    }
+                    pixels.push(if byte & (0x80 >> (x % 8)) != 0 { 0 } else { 1 });
+                }
+            }
+        }
+        _ => {
+            p.end_header()?;
+            for _ in 0..len {
+                let pixel = if max < 256 {
+                    p.byte()? as usize
+                } else {
+                    (p.byte()? as usize) << 8 | p.byte()? as usize
+                };
+                if pixel > max {
+                    return Err(corrupt("pixel brighter than the max gray value"));
+                }
+                pixels.push(pixel as u16);
+            }
+        }
+    }
+    Ok(Image { width, height, max: max as u16, pixels })
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // builds the tree bottom up, the bitmap is row major and true cells get the value
+    // the tree starts at 0,0 and its size is the smallest power of two fitting the bitmap
+    pub fn from_bitmap(width: usize, height: usize, bits: &[bool], value: T) -> Self {
+        assert!(bits.len() == width * height, "the bitmap must have width * height cells");
+        let size = width.max(height).max(1).next_power_of_two() as i32;
+        let mut tree = Quadtree::new(size,ivec2!(0,0));
+
+        // returns the node covering the square, groups of children are pushed to the arena only
+        // once it's known they can't be merged
+        fn build<T: PartialEq + Clone>(tree: &mut Quadtree<T>, pos: IVec2, size: i32, width: usize, height: usize, bits: &[bool], value: &T) -> QuadtreeNode<T> {
+            if pos.x as usize >= width || pos.y as usize >= height {
+                // padding outside of the bitmap
+                return QuadtreeNode::new(size,pos,None);
+            }
+            if size == 1 {
+                let full = bits[pos.y as usize * width + pos.x as usize];
+                return QuadtreeNode::new(size,pos,if full { Some(value.clone()) } else { None });
+            }
+            let half = size / 2;
+            let children = [
+                build(tree,pos,half,width,height,bits,value),
+                build(tree,ivec2!(pos.x + half, pos.y),half,width,height,bits,value),
+                build(tree,ivec2!(pos.x, pos.y + half),half,width,height,bits,value),
+                build(tree,ivec2!(pos.x + half, pos.y + half),half,width,height,bits,value),
+            ];
+            if children.iter().all(|child| child.is_leaf() && child.value == children[0].value) {
+                let [first, ..] = children;
+                return QuadtreeNode::new(size,pos,first.value);
+            }
+            let mut node = QuadtreeNode::new(size,pos,None);
+            node.children = Some(tree.nodes.len() as u32);
+            tree.nodes.extend(children);
+            node
+        }
+        let head = build(&mut tree,ivec2!(0,0),size,width,height,bits,&value);
+        tree.nodes[HEAD as usize] = head;
+        tree
+    }
+    // row major bitmap of the whole tree (size * size cells), true where a cell isn't empty
+    pub fn to_bitmap(&self) -> Vec<bool> {
+        let head = self.head();
+        let size = head.size as usize;
+        let mut bits = vec![false; size * size];
+        for node in self.leaves() {
+            if node.value.is_none() {
+                continue;
+            }
+            let rel_pos = node.pos() - head.pos();
+            for y in rel_pos.y..rel_pos.y + node.size {
+                for x in rel_pos.x..rel_pos.x + node.size {
+                    bits[y as usize * size + x as usize] = true;
+                }
+            }
+        }
+        bits
+    }
+    // reads a pbm or pgm image, the pixels darker than half gray become the value
+    pub fn from_netpbm(r: &mut impl Read, value: T) -> io::Result<Self> {
+        let image = read_netpbm(r)?;
+        Ok(Quadtree::from_bitmap(image.width,image.height,&image.to_bitmap(),value))
+    }
+    // reads a pgm image, the pixels darker than the threshold become the value
+    pub fn from_pgm(r: &mut impl Read, threshold: u16, value: T) -> io::Result<Self> {
+        let image = read_netpbm(r)?;
+        Ok(Quadtree::from_bitmap(image.width,image.height,&image.threshold(threshold),value))
+    }
+    // writes a binary pbm, non empty cells are black
+    pub fn write_pbm(&self, w: &mut impl Write) -> io::Result<()> {
+        let size = self.head().size as usize;
+        let bits = self.to_bitmap();
+        write!(w,"P4\n{} {}\n",size,size)?;
+        for row in bits.chunks(size) {
+            let mut packed = vec![0u8; size.div_ceil(8)];
+            for (x,bit) in row.iter().enumerate() {
+                if *bit {
+                    packed[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            w.write_all(&packed)?;
+        }
+        Ok(())
+    }
+    // writes a binary pgm with the shade of every cell
+    pub fn write_pgm(&self, w: &mut impl Write, shade: impl Fn(Option<&T>) -> u8) -> io::Result<()> {
+        let head = self.head();
+        let size = head.size as usize;
+        let mut pixels = vec![shade(None); size * size];
+        for node in self.leaves() {
+            let pixel = shade(node.value.as_ref());
+            let rel_pos = node.pos() - head.pos();
+            for y in rel_pos.y..rel_pos.y + node.size {
+                for x in rel_pos.x..rel_pos.x + node.size {
+                    pixels[y as usize * size + x as usize] = pixel;
+                }
+            }
+        }
+        write!(w,"P5\n{} {}\n255\n",size,size)?;
+        w.write_all(&pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sixteen_bit_pgm_keeps_its_dark_pixels() {
+        let mut data = b"P5 2 1 65535\n".to_vec();
+        data.extend_from_slice(&[0,0,0xFF,0xFF]);
+        let image = read_netpbm(&mut &data[..]).unwrap();
+        assert_eq!(image.to_bitmap(),vec![true,false]);
+    }
+}
@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+
+// a world made of a sparse grid of quadtrees of the same size, only the chunks holding something
+// are stored
+pub struct ChunkMap<T> {
+    // the chunks keyed by their chunk coordinate, the chunk (x,y) covers the cells from
+    // (x,y) * chunk_size to (x + 1,y + 1) * chunk_size
+    chunks: HashMap<(i32,i32),Quadtree<T>>,
+    pub chunk_size: i32,
+    // the box around the stored chunks, a ray leaving it can't hit anything anymore
+    min_chunk: (i32,i32),
+    max_chunk: (i32,i32),
+}
+impl<T: PartialEq + Clone> ChunkMap<T> {
+    pub fn new(chunk_size: i32) -> Self {
+        assert!(chunk_size > 0 && chunk_size & (chunk_size - 1) == 0, "the size of a chunk must be a power of two");
+        ChunkMap {
+            chunks: HashMap::new(),
+            chunk_size,
+            min_chunk: (0,0),
+            max_chunk: (0,0),
+        }
+    }
+    // the coordinate of the chunk containing the cell
+    pub fn chunk_coord(&self, pos: IVec2) -> (i32,i32) {
+        (pos.x.div_euclid(self.chunk_size), pos.y.div_euclid(self.chunk_size))
+    }
+    pub fn chunk(&self, coord: (i32,i32)) -> Option<&Quadtree<T>> {
+        self.chunks.get(&coord)
+    }
+    pub fn set(&mut self, pos: IVec2, value: T) {
+        let coord = self.chunk_coord(pos);
+        let size = self.chunk_size;
+        if self.chunks.is_empty() {
+            (self.min_chunk,self.max_chunk) = (coord,coord);
+        } else {
+            self.min_chunk = (self.min_chunk.0.min(coord.0),self.min_chunk.1.min(coord.1));
+            self.max_chunk = (self.max_chunk.0.max(coord.0),self.max_chunk.1.max(coord.1));
+        }
+        self.chunks.entry(coord)
+            .or_insert_with(|| Quadtree::new(size,ivec2!(coord.0 * size,coord.1 * size)))
+            .set(pos,value);
+    }
+    pub fn remove_block(&mut self, pos: IVec2) {
+        let coord = self.chunk_coord(pos);
+        let Some(chunk) = self.chunks.get_mut(&coord) else {
+            return;
+        };
+        chunk.remove_block(pos);
+        // a chunk that became empty is dropped
+        let head = chunk.head();
+        if head.is_leaf() && !head.is_full() {
+            self.chunks.remove(&coord);
+            // the box only shrinks when a chunk on its border goes
+            let on_border = coord.0 == self.min_chunk.0 || coord.0 == self.max_chunk.0
+                || coord.1 == self.min_chunk.1 || coord.1 == self.max_chunk.1;
+            if on_border {
+                self.update_bounds();
+            }
+        }
+    }
+    fn update_bounds(&mut self) {
+        let mut coords = self.chunks.keys();
+        let Some(&first) = coords.next() else {
+            return;
+        };
+        (self.min_chunk,self.max_chunk) = coords.fold((first,first),|(min,max),&(x,y)| {
+            ((min.0.min(x),min.1.min(y)),(max.0.max(x),max.1.max(y)))
+        });
+    }
+    pub fn get(&self, pos: IVec2) -> Option<&T> {
+        self.chunk(self.chunk_coord(pos))?.get(pos)
+    }
+    pub fn is_solid_at(&self, pos: IVec2) -> bool {
+        self.get(pos).is_some()
+    }
+    // returns the first non empty leaf hit by the ray closer than max_distance, the chunks are
+    // walked with a dda like dda_quad and the stored ones are traversed with the quadtree raycast
+    pub fn raycast2(&self, start: Vec2, dir: Vec2, max_distance: f32) -> Option<RayHit<'_,T>> {
+        if self.chunks.is_empty() {
+            return None;
+        }
+        let (min,max) = (self.min_chunk,self.max_chunk);
+        let size = self.chunk_size as f32;
+        let chunk_start = Vec2::new(start.x / size, start.y / size);
+
+        let mut chunk = ivec2!(chunk_start.x.floor() as i32, chunk_start.y.floor() as i32);
+        let step_dir = ivec2!(dir.x.signum() as i32, dir.y.signum() as i32);
+
+        // the t values are in world units so a chunk is size / |dir| long along the ray
+        let t_delta = Vec2::new(size / dir.x.abs(), size / dir.y.abs());
+
+        fn frac0(x: f32) -> f32 {
+            x - x.floor()
+        }
+        fn frac1(x: f32) -> f32 {
+            1. - frac0(x)
+        }
+
+        // a ray parallel to an axis never steps along it (inf * 0 would give NaN)
+        let mut t_max_x = if dir.x == 0. {
+            f32::INFINITY
+        } else if dir.x > 0. {
+            t_delta.x * frac1(chunk_start.x)
+        } else {
+            t_delta.x * frac0(chunk_start.x)
+        };
+        let mut t_max_y = if dir.y == 0. {
+            f32::INFINITY
+        } else if dir.y > 0. {
+            t_delta.y * frac1(chunk_start.y)
+        } else {
+            t_delta.y * frac0(chunk_start.y)
+        };
+
+        let mut traveled_distance = 0.0;
+        while traveled_distance < max_distance {
+            // out of the box and moving away from it
+            if (chunk.x < min.0 && dir.x <= 0.) || (chunk.x > max.0 && dir.x >= 0.)
+                || (chunk.y < min.1 && dir.y <= 0.) || (chunk.y > max.1 && dir.y >= 0.) {
+                return None;
+            }
+            if let Some(tree) = self.chunk((chunk.x,chunk.y)) {
+                if let Some(hit) = raycast_segment(start,dir,0.,max_distance,tree) {
+                    return Some(hit);
+                }
+            }
+
+            if t_max_x < t_max_y {
+                chunk.x += step_dir.x;
+                traveled_distance = t_max_x;
+                t_max_x += t_delta.x;
+            } else {
+                chunk.y += step_dir.y;
+                traveled_distance = t_max_y;
+                t_max_y += t_delta.y;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_missing_every_chunk_stops() {
+        let mut map = ChunkMap::new(16);
+        map.set(ivec2!(20,20),1_u8);
+        // passes below the chunk, parallel to an axis and diagonally away from it
+        assert!(map.raycast2(Vec2::new(0.5,0.5),Vec2::new(1.,0.),f32::INFINITY).is_none());
+        assert!(map.raycast2(Vec2::new(0.5,0.5),Vec2::new(1.,-0.3),f32::INFINITY).is_none());
+        assert!(map.raycast2(Vec2::new(40.5,40.5),Vec2::new(0.,1.),f32::INFINITY).is_none());
+        assert!(ChunkMap::<u8>::new(16).raycast2(Vec2::new(0.5,0.5),Vec2::new(1.,1.),f32::INFINITY).is_none());
+        // and still hits it when pointed at it
+        let hit = map.raycast2(Vec2::new(0.5,20.5),Vec2::new(1.,0.),f32::INFINITY).unwrap();
+        assert_eq!(hit.node.pos().x,20);
+    }
+
+    #[test]
+    fn bounds_follow_the_chunks() {
+        let mut map = ChunkMap::new(16);
+        map.set(ivec2!(20,20),1_u8);
+        map.set(ivec2!(-40,70),1_u8);
+        assert_eq!((map.min_chunk,map.max_chunk),((-3,1),(1,4)));
+        map.remove_block(ivec2!(-40,70));
+        assert_eq!((map.min_chunk,map.max_chunk),((1,1),(1,1)));
+        // the ray toward the removed chunk stops at the box of the one left
+        assert!(map.raycast2(Vec2::new(0.5,0.5),Vec2::new(-0.5,1.),f32::INFINITY).is_none());
+        map.remove_block(ivec2!(20,20));
+        assert!(map.raycast2(Vec2::new(0.5,20.5),Vec2::new(1.,0.),f32::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::sample_grid::*;
+use crate::spatial_tree::{root_params, min_t, max_t};
+
+// the distance from the point to the square of the node, 0 inside of it
+fn point_distance<T>(node: &QuadtreeNode<T>, p: Vec2) -> f32 {
+    let pos = node.pos();
+    let dx = (pos.x as f32 - p.x).max(p.x - (pos.x + node.size) as f32).max(0.);
+    let dy = (pos.y as f32 - p.y).max(p.y - (pos.y + node.size) as f32).max(0.);
+    (dx * dx + dy * dy).sqrt()
+}
+
+// the distance between the squares of two nodes, 0 if they touch or overlap
+fn node_distance<T>(a: &QuadtreeNode<T>, b: &QuadtreeNode<T>) -> f32 {
+    let (a_pos,b_pos) = (a.pos(),b.pos());
+    let dx = (a_pos.x - (b_pos.x + b.size)).max(b_pos.x - (a_pos.x + a.size)).max(0) as f32;
+    let dy = (a_pos.y - (b_pos.y + b.size)).max(b_pos.y - (a_pos.y + a.size)).max(0) as f32;
+    (dx * dx + dy * dy).sqrt()
+}
+
+// the distance to the nearest solid cell sampled at the center of every sample, negative inside of
+// solid cells
+pub type DistanceField = SampleGrid<f32>;
+
+// per node of the tree's arena a lower bound of the distance from any point of the node to the
+// nearest solid cell, 0 for the nodes holding solid cells
+// the bounds are only valid until the tree is changed
+pub struct DistanceBounds {
+    pub bounds: Vec<f32>,
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // lowers best to the distance of the closest leaf that is full (or empty if full is false),
+    // subtrees that can't be closer than best are skipped
+    fn nearest(&self, id: u32, full: bool, distance: &impl Fn(&QuadtreeNode<T>) -> f32, best: &mut f32) {
+        let node = &self.nodes[id as usize];
+        if distance(node) >= *best {
+            return;
+        }
+        let Some(first) = node.children else {
+            if node.is_full() == full {
+                *best = distance(node);
+            }
+            return;
+        };
+        // the closer children first so the others are more likely to get skipped
+        let mut children = [first, first + 1, first + 2, first + 3];
+        children.sort_by(|a,b| distance(&self.nodes[*a as usize]).total_cmp(&distance(&self.nodes[*b as usize])));
+        for child in children {
+            self.nearest(child,full,distance,best);
+        }
+    }
+    // the exact euclidean distance from the point to the nearest solid cell, or minus the distance
+    // to the nearest empty cell if the point is inside a solid one, outside of the tree is empty
+    pub fn distance_to(&self, p: Vec2) -> f32 {
+        let cell = ivec2!(p.x.floor() as i32, p.y.floor() as i32);
+        if !self.is_solid_at(cell) {
+            let mut best = f32::INFINITY;
+            self.nearest(HEAD,true,&|node| point_distance(node,p),&mut best);
+            return best;
+        }
+        // the space around the tree counts as empty
+        let head = self.head();
+        let pos = head.pos();
+        let mut best = (p.x - pos.x as f32).min((pos.x + head.size) as f32 - p.x)
+            .min(p.y - pos.y as f32).min((pos.y + head.size) as f32 - p.y);
+        self.nearest(HEAD,false,&|node| point_distance(node,p),&mut best);
+        -best
+    }
+    // the signed distance field of the tree with resolution * resolution samples per cell
+    pub fn distance_field(&self, resolution: i32) -> DistanceField {
+        let head = self.head();
+        let mut field = DistanceField::new(resolution,head.pos(),head.size,0.);
+        for y in 0..field.size {
+            for x in 0..field.size {
+                let index = (y * field.size + x) as usize;
+                field.values[index] = self.distance_to(field.sample_pos(ivec2!(x,y)));
+            }
+        }
+        field
+    }
+    // the adaptive version of the distance field, a bound per node instead of a value per sample
+    pub fn distance_bounds(&self) -> DistanceBounds {
+        let mut bounds = vec![0.; self.nodes.len()];
+        let mut stack = vec![HEAD];
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id as usize];
+            let mut best = f32::INFINITY;
+            self.nearest(HEAD,true,&|other| node_distance(node,other),&mut best);
+            bounds[id as usize] = best;
+            if let Some(first) = node.children {
+                stack.extend((0..4).map(|i| first + i));
+            }
+        }
+        DistanceBounds { bounds }
+    }
+    // sphere traces the ray through the tree, from every point it jumps by the bound of the leaf
+    // it's in or to the exit of that leaf, whichever is further
+    // returns the id of the first solid leaf hit and the t at which the ray enters it
+    pub fn sphere_trace(&self, field: &DistanceBounds, start: Vec2, dir: Vec2, t_max: f32) -> Option<(u32,f32)> {
+        let head = self.head();
+        let (_,t0,t1) = root_params([start.x,start.y],[dir.x,dir.y],head.position,head.size)?;
+        let t_exit = min_t(t1).min(t_max);
+        let mut t = max_t(t0).max(0.);
+
+        while t < t_exit {
+            let p = start + dir * t;
+            // on a cell boundary the ray is in the cell it moves into
+            let mut cell = ivec2!(p.x.floor() as i32, p.y.floor() as i32);
+            if dir.x < 0. && p.x == p.x.floor() {
+                cell.x -= 1;
+            }
+            if dir.y < 0. && p.y == p.y.floor() {
+                cell.y -= 1;
+            }
+            // between the root's entry and exit the point can only be out of the root by rounding
+            let root = head.pos();
+            let cell = ivec2!(cell.x.clamp(root.x,root.x + head.size - 1), cell.y.clamp(root.y,root.y + head.size - 1));
+            let leaf = self.leaf_at(cell).unwrap();
+            let node = &self.nodes[leaf as usize];
+            let node_pos = node.pos();
+            let lo = Vec2::new(node_pos.x as f32, node_pos.y as f32);
+            let hi = Vec2::new((node_pos.x + node.size) as f32, (node_pos.y + node.size) as f32);
+            if node.is_full() {
+                // the steps can overshoot a little so the entry is computed from the leaf itself
+                let enter_x = if dir.x > 0. { (lo.x - start.x) / dir.x } else if dir.x < 0. { (hi.x - start.x) / dir.x } else { f32::NEG_INFINITY };
+                let enter_y = if dir.y > 0. { (lo.y - start.y) / dir.y } else if dir.y < 0. { (hi.y - start.y) / dir.y } else { f32::NEG_INFINITY };
+                return Some((leaf,enter_x.max(enter_y).max(0.)));
+            }
+            let exit_x = if dir.x > 0. { (hi.x - p.x) / dir.x } else if dir.x < 0. { (lo.x - p.x) / dir.x } else { f32::INFINITY };
+            let exit_y = if dir.y > 0. { (hi.y - p.y) / dir.y } else if dir.y < 0. { (lo.y - p.y) / dir.y } else { f32::INFINITY };
+            let dir_len = (dir.x * dir.x + dir.y * dir.y).sqrt();
+            let step = exit_x.min(exit_y).max(field.bounds[leaf as usize] / dir_len);
+            // a step too small to move t would loop forever at a boundary
+            t = (t + step).max(t + t.abs() * f32::EPSILON);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raycast::*;
+
+    // a 16 * 16 tree with a few blocks of different sizes so the empty leaves are of all sizes too
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(2,3),ivec2!(4,4),1);
+        tree.fill_rect(ivec2!(8,8),ivec2!(12,12),1);
+        tree.set(ivec2!(13,2),1);
+        tree.set(ivec2!(5,13),1);
+        tree
+    }
+
+    // the distance from the point to the cell's square
+    fn cell_distance(cell: IVec2, p: Vec2) -> f32 {
+        let dx = (cell.x as f32 - p.x).max(p.x - (cell.x + 1) as f32).max(0.);
+        let dy = (cell.y as f32 - p.y).max(p.y - (cell.y + 1) as f32).max(0.);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    // the signed distance looking at every cell
+    fn brute_distance(tree: &Quadtree<u8>, p: Vec2) -> f32 {
+        let inside = tree.is_solid_at(ivec2!(p.x.floor() as i32,p.y.floor() as i32));
+        let mut best = if inside { p.x.min(16. - p.x).min(p.y).min(16. - p.y) } else { f32::INFINITY };
+        for y in 0..16 {
+            for x in 0..16 {
+                if tree.is_solid_at(ivec2!(x,y)) != inside {
+                    best = best.min(cell_distance(ivec2!(x,y),p));
+                }
+            }
+        }
+        if inside { -best } else { best }
+    }
+
+    #[test]
+    fn field_matches_a_cell_scan() {
+        let tree = test_tree();
+        let field = tree.distance_field(2);
+        assert_eq!(field.size,32);
+        for y in 0..field.size {
+            for x in 0..field.size {
+                let p = field.sample_pos(ivec2!(x,y));
+                assert!((field.at(ivec2!(x,y)) - brute_distance(&tree,p)).abs() < 1e-4,"at {:?}",(p.x,p.y));
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_are_the_distance_to_the_nearest_solid_cell() {
+        let tree = test_tree();
+        let field = tree.distance_bounds();
+        for (id,node) in tree.nodes.iter().enumerate() {
+            let pos = node.pos();
+            let mut best = f32::INFINITY;
+            for y in 0..16 {
+                for x in 0..16 {
+                    if tree.is_solid_at(ivec2!(x,y)) {
+                        let dx = (pos.x - (x + 1)).max(x - (pos.x + node.size)).max(0) as f32;
+                        let dy = (pos.y - (y + 1)).max(y - (pos.y + node.size)).max(0) as f32;
+                        best = best.min((dx * dx + dy * dy).sqrt());
+                    }
+                }
+            }
+            assert_eq!(field.bounds[id],best,"node at {:?} of size {}",(pos.x,pos.y),node.size);
+        }
+    }
+
+    #[test]
+    fn sphere_trace_matches_the_raycast() {
+        let tree = test_tree();
+        let field = tree.distance_bounds();
+        let mut seed = 0x1B873593_u32;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut hits = 0;
+        for _ in 0..500 {
+            // off the edges of the cells, a ray leaving a wall it starts on is only a hit for the raycast
+            let start = Vec2::new((rand() % 1600) as f32 / 100. + 0.005,(rand() % 1600) as f32 / 100. + 0.005);
+            if tree.is_solid_at(ivec2!(start.x.floor() as i32,start.y.floor() as i32)) {
+                continue;
+            }
+            let dir = Vec2::new((rand() % 2000) as f32 / 1000. - 1.,(rand() % 2000) as f32 / 1000. - 1.);
+            let traced = tree.sphere_trace(&field,start,dir,f32::INFINITY);
+            let cast = raycast2(start,dir,&tree).map(|hit| (hit.id,hit.t_enter));
+            match (traced,cast) {
+                (Some((a,t_a)),Some((b,t_b))) => {
+                    assert_eq!(a,b,"from {:?} along {:?}",(start.x,start.y),(dir.x,dir.y));
+                    assert!((t_a - t_b).abs() < 1e-3,"{} against {}",t_a,t_b);
+                    hits += 1;
+                }
+                (None,None) => {}
+                _ => panic!("from {:?} along {:?}, {:?} against {:?}",(start.x,start.y),(dir.x,dir.y),traced,cast),
+            }
+        }
+        assert!(hits > 50);
+    }
+}
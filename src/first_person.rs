@@ -0,0 +1,121 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+use crate::graphics::*;
+
+// how close the player can get to a wall
+const PLAYER_RADIUS: f32 = 0.2;
+// the distance at which the walls fade to half their colour
+const HALF_SHADE_DISTANCE: f32 = 12.;
+// how much darker the faces across the y axis are than the ones across the x axis
+const Y_FACE_SHADE: f32 = 0.7;
+const CEILING_COL: u32 = (40 << 16) + (40 << 8) + 48;
+const FLOOR_COL: u32 = (70 << 16) + (64 << 8) + 56;
+
+// the player of the first person view
+pub struct Camera {
+    pub position: Vec2,
+    // the angle of the view direction in radians from the x axis toward the y axis
+    pub angle: f32,
+    // the horizontal field of view in radians
+    pub fov: f32,
+}
+impl Camera {
+    pub fn new(position: Vec2, angle: f32, fov: f32) -> Self {
+        Camera { position, angle, fov }
+    }
+    pub fn dir(&self) -> Vec2 {
+        Vec2::new(self.angle.cos(),self.angle.sin())
+    }
+    pub fn turn(&mut self, angle: f32) {
+        self.angle = (self.angle + angle).rem_euclid(std::f32::consts::TAU);
+    }
+    // moves forward along the view and to the right of it, each axis is moved on its own so the
+    // player slides along the walls instead of stopping at them
+    // both corners of the side the player moves toward are tested so it can't cut a wall's corner,
+    // the cells outside of the root block it too
+    pub fn walk<T: PartialEq + Clone>(&mut self, forward: f32, right: f32, chunk_data: &Quadtree<T>) {
+        let dir = self.dir();
+        let side = Vec2::new(-dir.y,dir.x);
+        let step = dir * forward + side * right;
+        let blocked = |x: f32, y: f32| {
+            let cell = ivec2!(x.floor() as i32,y.floor() as i32);
+            chunk_data.leaf_at(cell).is_none() || chunk_data.is_solid_at(cell)
+        };
+
+        let x = self.position.x + step.x;
+        let edge = x + PLAYER_RADIUS.copysign(step.x);
+        let (y_min,y_max) = (self.position.y - PLAYER_RADIUS,self.position.y + PLAYER_RADIUS);
+        if step.x != 0. && !blocked(edge,y_min) && !blocked(edge,y_max) {
+            self.position.x = x;
+        }
+        let y = self.position.y + step.y;
+        let edge = y + PLAYER_RADIUS.copysign(step.y);
+        let (x_min,x_max) = (self.position.x - PLAYER_RADIUS,self.position.x + PLAYER_RADIUS);
+        if step.y != 0. && !blocked(x_min,edge) && !blocked(x_max,edge) {
+            self.position.y = y;
+        }
+    }
+    // draws the view into the whole framebuffer, every column casts a ray through its point of the
+    // camera plane and draws the slice of the first full leaf it hits in the leaf's colour
+    pub fn render<T: PartialEq + Clone>(&self, fb: &mut Framebuffer, chunk_data: &Quadtree<T>, color: impl Fn(&T) -> u32) {
+        let (width,height) = (fb.width as i32,fb.height as i32);
+        let dir = self.dir();
+        // the camera plane is one unit in front of the player and spans the fov
+        let plane = Vec2::new(-dir.y,dir.x) * (self.fov / 2.).tan();
+        for x in 0..width {
+            for y in 0..height {
+                fb.set_pixel(x,y,if y < height / 2 { CEILING_COL } else { FLOOR_COL });
+            }
+            let camera_x = 2. * (x as f32 + 0.5) / width as f32 - 1.;
+            let ray_dir = dir + plane * camera_x;
+            let Some(hit) = raycast2(self.position,ray_dir,chunk_data) else {
+                continue;
+            };
+            // the ray isn't normalized but reaches the camera plane at t = 1 so t is the distance
+            // along the view direction, using it instead of the euclidean distance keeps the walls
+            // from bulging toward the center of the view (the fisheye effect)
+            let distance = hit.t_enter.max(1e-3);
+            let slice = (height as f32 / distance) as i32;
+            let top = (height - slice) / 2;
+
+            let mut shade = HALF_SHADE_DISTANCE / (HALF_SHADE_DISTANCE + distance);
+            if matches!(hit.side,Side::YMin | Side::YMax) {
+                shade *= Y_FACE_SHADE;
+            }
+            let col = unsafe { blend_color(Color { col: BLACK },Color { col: color(hit.value) },shade).col };
+            for y in top.max(0)..(top + slice).min(height) {
+                fb.set_pixel(x,y,col);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_doesnt_cut_corners() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.set(ivec2!(8,8),1);
+        // the center line of the player passes below the wall but its upper corner doesn't
+        let mut camera = Camera::new(Vec2::new(7.7,7.9),0.,1.);
+        camera.walk(0.2,0.,&tree);
+        assert_eq!(camera.position.x,7.7);
+        // below it the player is free to move
+        let mut camera = Camera::new(Vec2::new(7.7,7.5),0.,1.);
+        camera.walk(0.2,0.,&tree);
+        assert!(camera.position.x > 7.8);
+    }
+
+    #[test]
+    fn walk_stays_in_the_root() {
+        let tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        // right of the x axis is toward +y
+        let mut camera = Camera::new(Vec2::new(0.5,0.5),0.,1.);
+        camera.walk(-0.5,-0.5,&tree);
+        assert_eq!((camera.position.x,camera.position.y),(0.5,0.5));
+    }
+}
@@ -134,6 +134,31 @@ impl Framebuffer {
         }
     }
 
+    // fills the pixels whose centers are inside the triangle, the winding doesn't matter
+    pub fn triangle(&mut self, a: (i32,i32), b: (i32,i32), c: (i32,i32), color: u32) {
+        // twice the signed area of the triangle p, q, r
+        let edge = |p: (i32,i32), q: (i32,i32), r: (i32,i32)| {
+            (q.0 - p.0) as i64 * (r.1 - p.1) as i64 - (q.1 - p.1) as i64 * (r.0 - p.0) as i64
+        };
+        let area = edge(a,b,c);
+        if area == 0 {
+            return;
+        }
+        let min_x = a.0.min(b.0).min(c.0).max(0);
+        let max_x = a.0.max(b.0).max(c.0).min(self.width as i32 - 1);
+        let min_y = a.1.min(b.1).min(c.1).max(0);
+        let max_y = a.1.max(b.1).max(c.1).min(self.height as i32 - 1);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let p = (x,y);
+                let (w0,w1,w2) = (edge(b,c,p),edge(c,a,p),edge(a,b,p));
+                if (w0 >= 0 && w1 >= 0 && w2 >= 0 && area > 0) || (w0 <= 0 && w1 <= 0 && w2 <= 0 && area < 0) {
+                    self.set_pixel(x,y,color);
+                }
+            }
+        }
+    }
+
     // Function for circle-generation
     // using Bresenham's algorithm
     pub fn circle(&mut self,xc: i32, yc: i32, r: i32,color:u32){
@@ -0,0 +1,150 @@
+#![allow(dead_code)]
+use std::f32::consts::TAU;
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+use crate::graphics::*;
+use crate::sample_grid::*;
+
+// the radius of the disc the rays of a light start from, the shadows get soft edges from the
+// parts of it an occluder hides
+const SOURCE_RADIUS: f32 = 0.35;
+// the angle between the origins of consecutive rays on the source disc
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+#[derive(Clone, Copy)]
+pub struct PointLight {
+    pub position: Vec2,
+    pub color: u32,
+    // the distance at which the light fades out completely
+    pub radius: f32,
+    // the exponent of the fading, 1 fades linearly
+    pub falloff: f32,
+}
+impl PointLight {
+    // the strength of the light at the distance from it
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        (1. - distance / self.radius).clamp(0.,1.).powf(self.falloff)
+    }
+    fn rgb(&self) -> [f32; 3] {
+        let color = Color { col: self.color };
+        unsafe { [color.ch.r as f32 / 255.,color.ch.g as f32 / 255.,color.ch.b as f32 / 255.] }
+    }
+}
+
+// the light reaching every sample of the tree, the channels are 0 for no light and 1 for the full
+// colour but can add up past it
+pub type Lightmap = SampleGrid<[f32; 3]>;
+impl Lightmap {
+    // multiplies the pixels the tree is drawn on by the light plus the ambient light, origin is
+    // the pixel of the tree's lower corner and cell_size the pixels per cell
+    pub fn blend_into(&self, fb: &mut Framebuffer, origin: (i32,i32), cell_size: i32, ambient: [f32; 3]) {
+        let pixels = self.size / self.resolution * cell_size;
+        for y in 0..pixels {
+            for x in 0..pixels {
+                let (pixel_x,pixel_y) = (origin.0 + x,origin.1 + y);
+                if !fb.pixel_fits(pixel_x,pixel_y) {
+                    continue;
+                }
+                let light = self.at(ivec2!(x * self.resolution / cell_size,y * self.resolution / cell_size));
+                let pixel = &mut fb.data[pixel_y as usize * fb.width + pixel_x as usize];
+                let mut color = Color { col: *pixel };
+                let scale = |channel: u8,i: usize| (channel as f32 * (light[i] + ambient[i])).min(255.) as u8;
+                unsafe {
+                    color.ch.r = scale(color.ch.r,0);
+                    color.ch.g = scale(color.ch.g,1);
+                    color.ch.b = scale(color.ch.b,2);
+                    *pixel = color.col;
+                }
+            }
+        }
+    }
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // the lightmap of the lights with resolution * resolution samples per cell
+    // every light casts rays_per_light rays evenly around it and walks them through the samples
+    // up to the first full leaf, each is blocked as seen from its own point of the light's source
+    // disc so the shadows get soft edges
+    // every step of a ray adds the area of its slice of the circle to the sample it's in so a
+    // sample ends up with the part of it the light reaches, the light's colour scaled by that
+    // and the attenuation is added
+    // the faces of the walls the rays end on are lit one sample deep
+    pub fn lightmap(&self, lights: &[PointLight], resolution: i32, rays_per_light: u32) -> Lightmap {
+        let head = self.head();
+        let mut map = Lightmap::new(resolution,head.pos(),head.size,[0.; 3]);
+        let step = 0.5 / resolution as f32;
+        let slice = TAU / rays_per_light as f32;
+        // the area of a sample in cells
+        let sample_area = 1. / (resolution * resolution) as f32;
+
+        let mut coverage = vec![0_f32; map.values.len()];
+        for light in lights {
+            coverage.fill(0.);
+            for i in 0..rays_per_light {
+                let angle = i as f32 * slice;
+                let dir = Vec2::new(angle.cos(),angle.sin());
+                // the light is spread along the line from the center but whether it's blocked is
+                // seen from the ray's point of the source
+                let spread = ((i as f32 + 0.5) / rays_per_light as f32).sqrt() * SOURCE_RADIUS;
+                let source_angle = i as f32 * GOLDEN_ANGLE;
+                let source = light.position + Vec2::new(source_angle.cos(),source_angle.sin()) * spread;
+                let to_end = light.position + dir * light.radius - source;
+                let length = (to_end.x * to_end.x + to_end.y * to_end.y).sqrt();
+                let end = match raycast_segment(source,to_end * (1. / length),0.,length,self) {
+                    // a ray starting in a wall gives no light
+                    Some(hit) if hit.t_enter <= 0. => continue,
+                    Some(hit) => {
+                        let to_hit = hit.point - light.position;
+                        to_hit.x * dir.x + to_hit.y * dir.y + 1. / resolution as f32
+                    }
+                    None => light.radius,
+                };
+                let mut t = step / 2.;
+                while t < end {
+                    if let Some(index) = map.index(light.position + dir * t) {
+                        coverage[index] += step * t * slice / sample_area;
+                    }
+                    t += step;
+                }
+            }
+            let rgb = light.rgb();
+            for y in 0..map.size {
+                for x in 0..map.size {
+                    let index = (y * map.size + x) as usize;
+                    if coverage[index] == 0. {
+                        continue;
+                    }
+                    let to_light = map.sample_pos(ivec2!(x,y)) - light.position;
+                    let distance = (to_light.x * to_light.x + to_light.y * to_light.y).sqrt();
+                    let strength = coverage[index].min(1.) * light.attenuation(distance);
+                    for (value,channel) in map.values[index].iter_mut().zip(rgb) {
+                        *value += channel * strength;
+                    }
+                }
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occluded_samples_get_less_light() {
+        let mut tree = Quadtree::new(32,ivec2!(0,0));
+        // a wall to the right of the light
+        tree.fill_rect(ivec2!(12,12),ivec2!(13,21),1_u8);
+        let light = PointLight { position: Vec2::new(8.5,16.5), color: WHITE, radius: 16., falloff: 1. };
+        let map = tree.lightmap(&[light],2,720);
+        // the same distance from the light on either side of it
+        let open = map.sample(Vec2::new(0.75,16.25)).unwrap();
+        let behind = map.sample(Vec2::new(16.25,16.25)).unwrap();
+        assert!(open[0] > 0.1);
+        assert!(behind[0] < open[0] * 0.1, "behind {} open {}",behind[0],open[0]);
+        // outside of the light's radius nothing is lit
+        assert_eq!(map.sample(Vec2::new(30.25,30.25)).unwrap(),[0.; 3]);
+    }
+}
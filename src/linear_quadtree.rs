@@ -0,0 +1,318 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::spatial_tree::{root_params, mid_t, first_node, next_node, child_t, entry_axis, min_t, max_t};
+
+// spreads the bits of x so there is a zero between each of them
+fn spread_bits(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8))  & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4))  & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2))  & 0x3333_3333_3333_3333;
+    x = (x | (x << 1))  & 0x5555_5555_5555_5555;
+    x
+}
+
+// the z-order code of a position relative to the root, x takes the low bit so the four children of
+// a node are ordered the same way as q0..q3
+pub fn morton(rel_pos: IVec2) -> u64 {
+    spread_bits(rel_pos.x as u32) | (spread_bits(rel_pos.y as u32) << 1)
+}
+
+fn area(size: i32) -> u64 {
+    size as u64 * size as u64
+}
+
+pub struct LinearLeaf<T> {
+    // the morton code of the lower corner, the leaf covers the codes code..code + size^2
+    pub code: u64,
+    pub size: i32,
+    pub position: IVec2,
+    pub value: T,
+}
+
+// the first leaf hit by a ray, the same as RayHit for the boxed quadtree
+pub struct LinearRayHit<'a, T> {
+    pub leaf: &'a LinearLeaf<T>,
+    pub value: &'a T,
+    // the t at which the ray enters the leaf, 0 if the ray starts inside of it
+    pub t_enter: f32,
+    // the t at which the ray leaves the leaf
+    pub t_exit: f32,
+    pub point: Vec2,
+    // unit normal of the entered side pointing out of the leaf
+    pub normal: Vec2,
+    // the side the ray entered through, if the ray starts inside the leaf it is
+    // the side it would have entered through
+    pub side: Side,
+}
+
+// what the node at a code and size is made of
+enum Kind {
+    Empty,
+    Leaf(usize),
+    Mixed,
+}
+
+// a quadtree that only stores its non empty leaves sorted by their morton code
+// the leaves of a node are a contiguous run of the array so every query is a binary search
+pub struct LinearQuadtree<T> {
+    pub leaves: Vec<LinearLeaf<T>>,
+    pub size: i32,
+    pub position: IVec2,
+}
+impl<T: PartialEq + Clone> LinearQuadtree<T> {
+    pub fn new(size: i32, pos: IVec2) -> Self {
+        let mut s = size ;
+        while s != 1 {
+            assert!(s % 2 == 0, "the size of the quad tree must be a power of two");
+            s /= 2;
+        }
+        LinearQuadtree {
+            leaves: Vec::new(),
+            size,
+            position: pos,
+        }
+    }
+    pub fn from_quadtree(tree: &Quadtree<T>) -> Self {
+        let mut out = LinearQuadtree::new(tree.head().size,tree.head().pos());
+        // a pre-order walk visits the children in q0..q3 order so the leaves come out sorted
+        fn collect<T: PartialEq + Clone>(tree: &Quadtree<T>, node: &QuadtreeNode<T>, out: &mut LinearQuadtree<T>) {
+            if let Some(children) = tree.children(node) {
+                for child in children {
+                    collect(tree,child,out);
+                }
+            } else if let Some(value) = &node.value {
+                out.leaves.push(LinearLeaf {
+                    code: morton(node.pos() - out.position),
+                    size: node.size,
+                    position: node.pos(),
+                    value: value.clone(),
+                });
+            }
+        }
+        collect(tree,tree.head(),&mut out);
+        out
+    }
+    pub fn to_quadtree(&self) -> Quadtree<T> {
+        let mut tree = Quadtree::new(self.size,self.position);
+        for leaf in &self.leaves {
+            let max = ivec2!(leaf.position.x + leaf.size, leaf.position.y + leaf.size);
+            tree.fill_rect(leaf.position,max,leaf.value.clone());
+        }
+        tree
+    }
+    fn contains(&self, pos: IVec2) -> bool {
+        !(pos.x < self.position.x || pos.x >= self.position.x + self.size ||
+            pos.y < self.position.y || pos.y >= self.position.y + self.size)
+    }
+    // the index of the leaf covering the code
+    fn find(&self, code: u64) -> Option<usize> {
+        let idx = self.leaves.partition_point(|leaf| leaf.code <= code);
+        if idx == 0 {
+            return None;
+        }
+        let leaf = &self.leaves[idx - 1];
+        if code < leaf.code + area(leaf.size) {
+            return Some(idx - 1);
+        }
+        None
+    }
+    fn kind(&self, code: u64, size: i32) -> Kind {
+        let idx = self.leaves.partition_point(|leaf| leaf.code < code);
+        if idx < self.leaves.len() && self.leaves[idx].code < code + area(size) {
+            if self.leaves[idx].code == code && self.leaves[idx].size == size {
+                return Kind::Leaf(idx);
+            }
+            return Kind::Mixed;
+        }
+        Kind::Empty
+    }
+    pub fn get(&self, pos: IVec2) -> Option<&T> {
+        if !self.contains(pos) {
+            return None;
+        }
+        self.find(morton(pos - self.position)).map(|idx| &self.leaves[idx].value)
+    }
+    pub fn is_solid_at(&self, pos: IVec2) -> bool {
+        self.get(pos).is_some()
+    }
+    pub fn set(&mut self, pos: IVec2, value: T) {
+        if !self.contains(pos) {
+            return;
+        }
+        self.set_value(pos,Some(value));
+    }
+    pub fn remove_block(&mut self, pos: IVec2) {
+        if !self.contains(pos) {
+            return;
+        }
+        self.set_value(pos,None);
+    }
+    fn set_value(&mut self, pos: IVec2, value: Option<T>) {
+        let code = morton(pos - self.position);
+
+        if let Some(idx) = self.find(code) {
+            if Some(&self.leaves[idx].value) == value.as_ref() {
+                return;
+            }
+            // split the leaf down to the cell, the pieces stay in the leaf's code range so they
+            // replace it in place
+            let leaf = self.leaves.remove(idx);
+            let mut pieces = Vec::new();
+            let mut size = leaf.size;
+            let mut piece_pos = leaf.position;
+            while size != 1 {
+                let half = size / 2;
+                let mut next_pos = piece_pos;
+                for i in 0..4 {
+                    let child_pos = ivec2!(piece_pos.x + (i & 1) * half, piece_pos.y + (i >> 1) * half);
+                    let rel_pos = pos - child_pos;
+                    if rel_pos.x >= 0 && rel_pos.x < half && rel_pos.y >= 0 && rel_pos.y < half {
+                        next_pos = child_pos;
+                    } else {
+                        pieces.push(LinearLeaf {
+                            code: morton(child_pos - self.position),
+                            size: half,
+                            position: child_pos,
+                            value: leaf.value.clone(),
+                        });
+                    }
+                }
+                piece_pos = next_pos;
+                size = half;
+            }
+            pieces.sort_by_key(|piece| piece.code);
+            self.leaves.splice(idx..idx,pieces);
+        }
+
+        let Some(value) = value else {
+            // empty space isn't stored so there is nothing to merge
+            return;
+        };
+        let idx = self.leaves.partition_point(|leaf| leaf.code < code);
+        self.leaves.insert(idx,LinearLeaf { code, size: 1, position: pos, value });
+
+        // merge the four siblings into their parent while they hold the same value
+        let mut size = 1;
+        while size != self.size {
+            let parent_area = area(size * 2);
+            let parent_code = code - code % parent_area;
+            let first = self.leaves.partition_point(|leaf| leaf.code < parent_code);
+            if first + 4 > self.leaves.len() {
+                return;
+            }
+            for i in 0..4 {
+                let leaf = &self.leaves[first + i];
+                if leaf.size != size || leaf.code != parent_code + i as u64 * area(size) ||
+                    leaf.value != self.leaves[first].value {
+                    return;
+                }
+            }
+            let position = self.leaves[first].position;
+            let value = self.leaves.drain(first..first + 4).next().unwrap().value;
+            size *= 2;
+            self.leaves.insert(first,LinearLeaf { code: parent_code, size, position, value });
+        }
+    }
+    // returns the first non empty leaf hit by the ray
+    pub fn raycast2(&self, start: Vec2, dir: Vec2) -> Option<LinearRayHit<'_,T>> {
+        let origin = [start.x,start.y];
+        let ray_dir = [dir.x,dir.y];
+        let (mask,t0,t1) = root_params(origin,ray_dir,[self.position.x,self.position.y],self.size)?;
+        let (idx,t0,t1) = self.proc_subtree(origin,ray_dir,mask,0,self.size,self.position,t0,t1)?;
+        let leaf = &self.leaves[idx];
+        let axis = entry_axis(t0);
+        // in the mirrored space the ray always enters through a lower side
+        let side = Side::from_axis(axis,mask & (1 << axis) != 0);
+        let t_enter = max_t(t0).max(0.);
+        Some(LinearRayHit {
+            leaf,
+            value: &leaf.value,
+            t_enter,
+            t_exit: min_t(t1),
+            point: start + dir * t_enter,
+            normal: side.normal(),
+            side,
+        })
+    }
+    // returns the index of the leaf and its t planes
+    #[allow(clippy::too_many_arguments)]
+    fn proc_subtree(&self, start: [f32; 2], dir: [f32; 2], mask: usize, code: u64, size: i32, pos: IVec2, t0: [f32; 2], t1: [f32; 2]) -> Option<(usize,[f32; 2],[f32; 2])> {
+        if !( t1[0] > 0. && t1[1] > 0. ) {
+            return None;
+        }
+        match self.kind(code,size) {
+            Kind::Empty => return None,
+            Kind::Leaf(idx) => return Some((idx,t0,t1)),
+            Kind::Mixed => (),
+        }
+
+        let half = size / 2;
+        let tm = [
+            mid_t(t0[0],t1[0],start[0],dir[0],pos.x as f32 + half as f32),
+            mid_t(t0[1],t1[1],start[1],dir[1],pos.y as f32 + half as f32),
+        ];
+
+        let mut curr_node = Some(first_node(t0,tm));
+        while let Some(curr) = curr_node {
+            let child = (curr ^ mask) as i32;
+            let child_code = code + child as u64 * area(half);
+            let child_pos = ivec2!(pos.x + (child & 1) * half, pos.y + (child >> 1) * half);
+            let (c0,c1) = child_t(curr,t0,tm,t1);
+            if let Some(hit) = self.proc_subtree(start,dir,mask,child_code,half,child_pos,c0,c1) {
+                return Some(hit);
+            }
+            curr_node = next_node(curr,c1);
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raycast::raycast2;
+
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(-4,0));
+        tree.fill_rect(ivec2!(4,4),ivec2!(8,8),1);
+        tree.fill_rect(ivec2!(-4,8),ivec2!(0,12),2);
+        tree.set(ivec2!(0,3),3);
+        tree.set(ivec2!(9,13),1);
+        tree
+    }
+
+    #[test]
+    fn conversion_is_lossless() {
+        let tree = test_tree();
+        let linear = LinearQuadtree::from_quadtree(&tree);
+        assert_eq!(linear.leaves.len(),tree.leaves().filter(|leaf| leaf.value.is_some()).count());
+        let back = linear.to_quadtree();
+        let leaves = |tree: &Quadtree<u8>| tree.leaves().map(|leaf| (leaf.pos(),leaf.size,leaf.value)).collect::<Vec<_>>();
+        assert_eq!(leaves(&back),leaves(&tree));
+    }
+
+    #[test]
+    fn raycast_matches_the_quadtree() {
+        let tree = test_tree();
+        let linear = LinearQuadtree::from_quadtree(&tree);
+        for i in 0..64 {
+            let angle = i as f32 * std::f32::consts::TAU / 64.;
+            let dir = Vec2::new(angle.cos(),angle.sin());
+            for start in [Vec2::new(2.5,2.5),Vec2::new(-3.5,15.5),Vec2::new(11.2,0.7),Vec2::new(-10.,6.)] {
+                let hit = raycast2(start,dir,&tree);
+                let linear_hit = linear.raycast2(start,dir);
+                let (Some(hit),Some(linear_hit)) = (&hit,&linear_hit) else {
+                    assert_eq!(hit.is_none(),linear_hit.is_none(), "start ({},{}) angle {}",start.x,start.y,angle);
+                    continue;
+                };
+                assert_eq!((hit.node.pos(),hit.node.size),(linear_hit.leaf.position,linear_hit.leaf.size));
+                assert_eq!(hit.value,linear_hit.value);
+                assert_eq!((hit.t_enter,hit.t_exit),(linear_hit.t_enter,linear_hit.t_exit));
+                assert_eq!(hit.side,linear_hit.side);
+            }
+        }
+    }
+}
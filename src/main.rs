@@ -1,6 +1,21 @@
 mod graphics;
 mod quadtree;
 mod raycast;
+mod linear_quadtree;
+mod quadtree_io;
+mod bitmap;
+mod octree;
+mod spatial_tree;
+mod chunk_map;
+mod regions;
+mod pathfinding;
+mod distance_field;
+mod sample_grid;
+mod shape_cast;
+mod visibility;
+mod reflection;
+mod lighting;
+mod first_person;
 
 use my_math::prelude::*;
 use minifb::MouseMode;
@@ -11,36 +26,98 @@ extern crate my_math;
 use graphics::*;
 use quadtree::*;
 use raycast::*;
+use reflection::*;
+use lighting::*;
+use first_person::*;
+use linear_quadtree::*;
 
 use std::time::Instant;
 use std::env;
 
 const BG_COL: u32 = (51 << 16) + (76 << 8) + 76;
 
+// the materials that can be painted into the quadtree, the value stored is the fill color
+const MATERIALS: [u32; 4] = [BLACK, (90 << 16) + (90 << 8) + 90, (120 << 16) + (200 << 8) + 230, (30 << 16) + (60 << 8) + 160];
+// the fraction of a ray's energy each material reflects
+const REFLECTIVITY: [f32; 4] = [0., 0.5, 0.95, 0.8];
+// the number of times the drawn ray bounces at most with -l
+const MAX_BOUNCES: u32 = 16;
+// the colours the lights placed with -p cycle through
+const LIGHT_COLORS: [u32; 4] = [(255 << 16) + (200 << 8) + 120, (120 << 16) + (160 << 8) + 255, (255 << 16) + (90 << 8) + 90, (120 << 16) + (255 << 8) + 140];
+const AMBIENT: [f32; 3] = [0.15,0.15,0.2];
+// the distance the player walks and the angle it turns per frame with -w
+const WALK_SPEED: f32 = 0.08;
+const TURN_SPEED: f32 = 0.04;
+
 static mut RES: i32 = 720;
 static mut TREE_RES:i32 = unsafe { RES * 9/10 };
 static mut HEIGHT: i32 = 1 << 6;
 static mut CELL_SIZE: i32 = unsafe { TREE_RES/HEIGHT };
+// the lower corner of the tree's root, the cell drawn at the top left
+static mut ROOT_X: i32 = 0;
+static mut ROOT_Y: i32 = 0;
 
-fn from_cell(pos:i32) -> i32 {
-    unsafe { (pos * CELL_SIZE) + ((RES - HEIGHT * CELL_SIZE) / 2) }
+fn from_cell(pos:IVec2) -> (i32,i32) {
+    unsafe {
+        let margin = (RES - HEIGHT * CELL_SIZE) / 2;
+        ((pos.x - ROOT_X) * CELL_SIZE + margin,(pos.y - ROOT_Y) * CELL_SIZE + margin)
+    }
+}
+fn from_cell_f32(pos:Vec2) -> (i32,i32) {
+    unsafe {
+        let margin = (RES - HEIGHT * CELL_SIZE) / 2;
+        (((pos.x - ROOT_X as f32) * CELL_SIZE as f32).round() as i32 + margin,((pos.y - ROOT_Y as f32) * CELL_SIZE as f32).round() as i32 + margin)
+    }
 }
-fn from_cell_f32(pos:f32) -> i32 {
-    unsafe { (pos * CELL_SIZE as f32).round() as i32 + ((RES - HEIGHT * CELL_SIZE) / 2) }
+fn into_cell(x:f32,y:f32) -> Vec2 {
+    unsafe {
+        let margin = ((RES - HEIGHT * CELL_SIZE) / 2) as f32;
+        Vec2::new((x - margin) / CELL_SIZE as f32 + ROOT_X as f32,(y - margin) / CELL_SIZE as f32 + ROOT_Y as f32)
+    }
 }
-fn into_cell(pos:f32) -> f32 {
-    unsafe { (pos - ((RES - HEIGHT * CELL_SIZE) / 2) as f32) / CELL_SIZE as f32 }
+// lays the window out for the root of the tree, a growable tree can change it every frame
+fn update_layout<T>(head: &QuadtreeNode<T>) {
+    unsafe {
+        HEIGHT = head.size;
+        CELL_SIZE = (TREE_RES/HEIGHT).max(1);
+        ROOT_X = head.pos().x;
+        ROOT_Y = head.pos().y;
+    }
 }
 
-fn parse_args() -> (bool,bool) {
+// the options given on the command line
+#[derive(Default)]
+struct Args {
+    // -f starts with a full tree
+    full: bool,
+    // -a highlights every leaf the ray passes
+    multi_hit: bool,
+    // -b runs the benchmark instead of opening the window
+    bench: bool,
+    // -g lets the tree grow past its root
+    growable: bool,
+    // -v draws the area visible from the cursor
+    visibility: bool,
+    // -l bounces the ray off the walls
+    bounces: bool,
+    // -p lights the tree
+    lighting: bool,
+    // -w opens the first person view
+    first_person: bool,
+    // -m loads and saves the map at the path
+    map_path: Option<String>,
+    // -i loads the tree from a netpbm image
+    image_path: Option<String>,
+}
+
+fn parse_args() -> Args {
     let args:Vec<String> = env::args().skip(1).collect();
-    let mut full = false;
-    let mut multi_hit = false;
+    let mut out = Args::default();
     let mut i = 0;
     while i < args.len() {
         match args[i].as_str() {
             "-f" => {
-                full = true;
+                out.full = true;
                 i += 1;
             }
             "-r" => { unsafe {
@@ -58,9 +135,41 @@ fn parse_args() -> (bool,bool) {
                 i += 2;
             }}
             "-a" => { 
-                multi_hit = true;
+                out.multi_hit = true;
+                i += 1;
+            }
+            "-b" => { 
+                out.bench = true;
+                i += 1;
+            }
+            "-w" => { 
+                out.first_person = true;
+                i += 1;
+            }
+            "-p" => { 
+                out.lighting = true;
+                i += 1;
+            }
+            "-l" => { 
+                out.bounces = true;
+                i += 1;
+            }
+            "-v" => { 
+                out.visibility = true;
+                i += 1;
+            }
+            "-g" => { 
+                out.growable = true;
                 i += 1;
             }
+            "-m" => { 
+                out.map_path = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "-i" => { 
+                out.image_path = Some(args[i + 1].clone());
+                i += 2;
+            }
             _ => panic!("not a valid flag \"{}\"",args[i]),
         }
     }
@@ -69,7 +178,7 @@ fn parse_args() -> (bool,bool) {
         TREE_RES =  RES * 9/10 ;
         CELL_SIZE =  TREE_RES/HEIGHT ;
     }
-    return (full,multi_hit);
+    return out;
 }
 
 
@@ -79,35 +188,176 @@ fn clear_screen() {
     std::io::stdout().flush().unwrap();
 }
 
+// times the boxed quadtree against the linear one on the same random map
+fn benchmark() {
+    let size = unsafe { HEIGHT };
+    let mut quadtree = Quadtree::new(size,ivec2!(0,0));
+    let mut linear = LinearQuadtree::new(size,ivec2!(0,0));
+
+    // xorshift so both trees get the same cells without pulling in a dependency
+    let mut seed: u32 = 0x9E3779B9;
+    let mut rand = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    let cells: Vec<IVec2> = (0..size * size / 4)
+        .map(|_| ivec2!((rand() % size as u32) as i32, (rand() % size as u32) as i32))
+        .collect();
+    let rays: Vec<(Vec2,Vec2)> = (0..10000)
+        .map(|_| {
+            let start = Vec2::new((rand() % size as u32) as f32 + 0.5, (rand() % size as u32) as f32 + 0.5);
+            let angle = (rand() % 3600) as f32 / 3600. * std::f32::consts::TAU;
+            (start, Vec2::new(angle.cos(), angle.sin()))
+        })
+        .collect();
+
+    let start = Instant::now();
+    for (i,cell) in cells.iter().enumerate() {
+        quadtree.set(*cell,(i % 3) as u8);
+    }
+    println!("boxed  set          {:?}",start.elapsed());
+    let start = Instant::now();
+    for (i,cell) in cells.iter().enumerate() {
+        linear.set(*cell,(i % 3) as u8);
+    }
+    println!("linear set          {:?}",start.elapsed());
+
+    let start = Instant::now();
+    let mut solid = 0;
+    for cell in &cells {
+        solid += quadtree.is_solid_at(*cell) as i32;
+    }
+    println!("boxed  is_solid_at  {:?} ({})",start.elapsed(),solid);
+    let start = Instant::now();
+    let mut solid = 0;
+    for cell in &cells {
+        solid += linear.is_solid_at(*cell) as i32;
+    }
+    println!("linear is_solid_at  {:?} ({})",start.elapsed(),solid);
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for (origin,dir) in &rays {
+        hits += raycast2(*origin,*dir,&quadtree).is_some() as i32;
+    }
+    println!("boxed  raycast2     {:?} ({})",start.elapsed(),hits);
+    let start = Instant::now();
+    let mut hits = 0;
+    for (origin,dir) in &rays {
+        hits += linear.raycast2(*origin,*dir).is_some() as i32;
+    }
+    println!("linear raycast2     {:?} ({})",start.elapsed(),hits);
+    let bounds = quadtree.distance_bounds();
+    let start = Instant::now();
+    let mut hits = 0;
+    for (origin,dir) in &rays {
+        hits += quadtree.sphere_trace(&bounds,*origin,*dir,f32::INFINITY).is_some() as i32;
+    }
+    println!("boxed  sphere_trace {:?} ({})",start.elapsed(),hits);
+
+    let start = Instant::now();
+    for (i,cell) in cells.iter().enumerate() {
+        if i % 2 == 0 {
+            quadtree.remove_block(*cell);
+        }
+    }
+    println!("boxed  remove_block {:?}",start.elapsed());
+    let start = Instant::now();
+    for (i,cell) in cells.iter().enumerate() {
+        if i % 2 == 0 {
+            linear.remove_block(*cell);
+        }
+    }
+    println!("linear remove_block {:?}",start.elapsed());
+
+    let start = Instant::now();
+    let converted = LinearQuadtree::from_quadtree(&quadtree);
+    println!("boxed -> linear     {:?} ({} leaves)",start.elapsed(),converted.leaves.len());
+    let start = Instant::now();
+    linear.to_quadtree();
+    println!("linear -> boxed     {:?}",start.elapsed());
+}
+
 fn main() {
-    let (full,multi_hit) = parse_args();
+    let Args { full,multi_hit,bench,growable,visibility,bounces,lighting,first_person,map_path,image_path } = parse_args();
+    if bench {
+        benchmark();
+        return;
+    }
     let mut quadtree;
     if full {
-        quadtree = unsafe { quadtree::Quadtree::new_full(HEIGHT,ivec2!(0,0)) };
+        quadtree = unsafe { quadtree::Quadtree::new_full(HEIGHT,ivec2!(0,0),MATERIALS[0]) };
     } else {
         quadtree = unsafe { quadtree::Quadtree::new(HEIGHT,ivec2!(0,0)) };
     }
+    if let Some(path) = &map_path {
+        if let Ok(file) = std::fs::File::open(path) {
+            quadtree = Quadtree::read_from(&mut std::io::BufReader::new(file)).unwrap_or_else(|err| {
+                panic!("|| couldn't load the map {} ({})||",path,err);
+            });
+        }
+    }
+    if let Some(path) = &image_path {
+        let file = std::fs::File::open(path).unwrap_or_else(|err| {
+            panic!("|| couldn't open the image {} ({})||",path,err);
+        });
+        quadtree = Quadtree::from_netpbm(&mut std::io::BufReader::new(file),MATERIALS[0]).unwrap_or_else(|err| {
+            panic!("|| couldn't load the image {} ({})||",path,err);
+        });
+    }
+    quadtree.set_growable(growable);
+    // the window is laid out for the size of a loaded map
+    update_layout(quadtree.head());
 
     let mut app = unsafe { App::new("raycast", RES, RES) };
-    let mut target_x = unsafe { RES as f32/2. + 1e-5 };
-    let mut target_y = unsafe { RES as f32/2. - 1e-5 };
+    let mut target_x = unsafe { RES as f32/2. };
+    let mut target_y = unsafe { RES as f32/2. };
+    let mut material = MATERIALS[0];
+    let mut lights: Vec<PointLight> = Vec::new();
+    // the first person view gets its own window
+    let mut view = first_person.then(|| unsafe { App::new("first person", RES, RES * 3/4) });
+    let mut camera = unsafe { Camera::new(Vec2::new(HEIGHT as f32 / 2.,HEIGHT as f32 / 2.),0.,std::f32::consts::FRAC_PI_2 * 2./3.) };
 
     'draw_loop: while app.window.is_open() {
+        update_layout(quadtree.head());
         let fb = &mut app.framebuffer;
 
         fb.clear(BG_COL);
 
-        quadtree.draw_outline(fb);
+        if visibility {
+            if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
+                let eye = into_cell(mouse_x,mouse_y);
+                let polygon = quadtree.visibility_polygon(eye,unsafe { HEIGHT as f32 });
+                for i in 0..polygon.len() {
+                    let next = polygon[(i + 1) % polygon.len()];
+                    fb.triangle(from_cell_f32(eye),from_cell_f32(polygon[i]),from_cell_f32(next),(90 << 16) + (110 << 8) + 90);
+                }
+            }
+        }
 
-        if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
-            let cell_mouse_x = into_cell(mouse_x);
-            let cell_mouse_y = into_cell(mouse_y);
-            let cell_target_x = into_cell(target_x);
-            let cell_target_y = into_cell(target_y);
+        quadtree.draw_outline(fb,|col| *col);
+
+        if lighting {
+            // the placed lights and one following the cursor
+            let mut all_lights = lights.clone();
+            if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
+                all_lights.push(PointLight {
+                    position: into_cell(mouse_x,mouse_y),
+                    color: WHITE,
+                    radius: unsafe { HEIGHT as f32 / 2. },
+                    falloff: 2.,
+                });
+            }
+            let lightmap = quadtree.lightmap(&all_lights,2,720);
+            let head = quadtree.head().pos();
+            lightmap.blend_into(fb,from_cell(head),unsafe { CELL_SIZE },AMBIENT);
+        }
 
-            let ray_origin = Vec2{ x: cell_mouse_x, y: cell_mouse_y};
-            let ray_dir = Vec2{ x: cell_target_x - cell_mouse_x, 
-                                y: cell_target_y - cell_mouse_y }.norm();
+        if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
+            let ray_origin = into_cell(mouse_x,mouse_y);
+            let ray_dir = (into_cell(target_x,target_y) - ray_origin).norm();
 
             let start = Instant::now();
             for _ in 0..1000 {
@@ -123,27 +373,47 @@ fn main() {
 
 
             if multi_hit {
-                let collitions = raycast(ray_origin,ray_dir,&quadtree);
+                let collitions: Vec<_> = raycast(ray_origin,ray_dir,&quadtree).collect();
 
-                for (i,node) in collitions.iter().enumerate() {
+                for (i,(node,_,_)) in collitions.iter().enumerate() {
                     let red = Color { col: 0x00FF0000 };
                     let blue = Color { col: 0x000000FF };
                     unsafe { 
                         let col = blend_color(blue,red, i as f32 / collitions.len() as f32).col;
-                        fb.square(from_cell(node.position.x) ,from_cell(node.position.y), node.size * CELL_SIZE , col);
+                        let (x,y) = from_cell(node.pos());
+                        fb.square(x,y, node.size * CELL_SIZE , col);
                     }
                 }
 
             } else {
                 let collition = raycast2(ray_origin,ray_dir,&quadtree);
                 
-                if let Some((first_node,t)) = collition {
+                if let Some(hit) = collition {
+                    let first_node = hit.node;
                     unsafe {
-                        fb.empty_square(from_cell(first_node.position.x) ,   from_cell(first_node.position.y) , first_node.size * CELL_SIZE , GREEN);
-                        fb.empty_square(from_cell(first_node.position.x) + 1,from_cell(first_node.position.y) + 1, first_node.size * CELL_SIZE -2, GREEN);
+                        let (x,y) = from_cell(first_node.pos());
+                        fb.empty_square(x    ,y    , first_node.size * CELL_SIZE , GREEN);
+                        fb.empty_square(x + 1,y + 1, first_node.size * CELL_SIZE -2, GREEN);
+                    }
+                    let hit_pos = hit.point;
+                    let normal_end = hit_pos + hit.normal * 2.;
+                    let (hit_x,hit_y) = from_cell_f32(hit_pos);
+                    let (end_x,end_y) = from_cell_f32(normal_end);
+                    fb.line(hit_x,hit_y,end_x,end_y,YELLOW);
+                    fb.circle(hit_x,hit_y,4,PINK);
+                }
+                if bounces {
+                    let reflectivity = |material: &u32| {
+                        MATERIALS.iter().position(|m| m == material).map_or(0.,|i| REFLECTIVITY[i])
+                    };
+                    let path = trace_bounces(ray_origin,ray_dir,MAX_BOUNCES,unsafe { HEIGHT as f32 * 4. },reflectivity,&quadtree);
+                    for segment in path {
+                        // the weaker the ray the more it fades into the background
+                        let col = unsafe { blend_color(Color { col: BG_COL },Color { col: YELLOW },segment.energy).col };
+                        let (start_x,start_y) = from_cell_f32(segment.start);
+                        let (end_x,end_y) = from_cell_f32(segment.end);
+                        fb.line(start_x,start_y,end_x,end_y,col);
                     }
-                    let hit_pos = ray_origin + ray_dir * t;
-                    fb.circle(from_cell_f32(hit_pos.x),from_cell_f32(hit_pos.y),4,PINK);
                 }
             }
 
@@ -154,25 +424,42 @@ fn main() {
             fb.line(mouse_x,mouse_y,target_x.round() as i32,target_y.round() as i32,WHITE);
         }
 
+        if let Some(view) = &mut view {
+            camera.render(&mut view.framebuffer,&quadtree,|col| *col);
+            view.display();
+
+            // the player with its field of view in the top down view
+            let fov_end = |angle: f32| camera.position + Vec2::new(angle.cos(),angle.sin()) * 3.;
+            let player = from_cell_f32(camera.position);
+            for end in [fov_end(camera.angle - camera.fov / 2.),fov_end(camera.angle + camera.fov / 2.)] {
+                let (end_x,end_y) = from_cell_f32(end);
+                fb.line(player.0,player.1,end_x,end_y,YELLOW);
+            }
+            fb.circle(player.0,player.1,6,YELLOW);
+        }
+
         use minifb::MouseButton;
         if app.window.get_mouse_down(MouseButton::Left) {
             if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
-                let grid_x = into_cell(mouse_x).floor() as i32;
-                let grid_y = into_cell(mouse_y).floor() as i32;
-                quadtree.add_block(ivec2!(grid_x,grid_y));
+                let cell = into_cell(mouse_x,mouse_y);
+                let grid_x = cell.x.floor() as i32;
+                let grid_y = cell.y.floor() as i32;
+                quadtree.set(ivec2!(grid_x,grid_y),material);
             }
         }
         if app.window.get_mouse_down(MouseButton::Right) {
             if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
-                let grid_x = into_cell(mouse_x).floor() as i32;
-                let grid_y = into_cell(mouse_y).floor() as i32;
+                let cell = into_cell(mouse_x,mouse_y);
+                let grid_x = cell.x.floor() as i32;
+                let grid_y = cell.y.floor() as i32;
                 quadtree.remove_block(ivec2!(grid_x ,grid_y ));
             }
         }
         if app.window.get_mouse_down(MouseButton::Middle) {
             if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
-                let grid_x = into_cell(mouse_x).floor() as i32;
-                let grid_y = into_cell(mouse_y).floor() as i32;
+                let cell = into_cell(mouse_x,mouse_y);
+                let grid_x = cell.x.floor() as i32;
+                let grid_y = cell.y.floor() as i32;
                 //grid[grid_x][grid_y] = false;
                 let mut out = String::new();
                 if quadtree.is_solid_at(ivec2!(grid_x ,grid_y )) {
@@ -197,15 +484,62 @@ fn main() {
                 Key::Right => target_x += 5.,
                 Key::Up => target_y    -= 5.,
                 Key::Down => target_y  += 5.,
+                Key::Key1 => material = MATERIALS[0],
+                Key::Key2 => material = MATERIALS[1],
+                Key::Key3 => material = MATERIALS[2],
+                Key::Key4 => material = MATERIALS[3],
                 _ => (),
             }
         }
+        if view.is_some() {
+            use minifb::Key;
+            // the keys work in both windows
+            let mut keys = app.window.get_keys();
+            keys.extend(view.as_ref().map_or(Vec::new(),|view| view.window.get_keys()));
+            for key in keys {
+                match key {
+                    Key::W => camera.walk(WALK_SPEED,0.,&quadtree),
+                    Key::S => camera.walk(-WALK_SPEED,0.,&quadtree),
+                    Key::Q => camera.walk(0.,-WALK_SPEED,&quadtree),
+                    Key::E => camera.walk(0.,WALK_SPEED,&quadtree),
+                    Key::A => camera.turn(-TURN_SPEED),
+                    Key::D => camera.turn(TURN_SPEED),
+                    _ => (),
+                }
+            }
+        }
+        if lighting {
+            use minifb::{Key,KeyRepeat};
+            if app.window.is_key_pressed(Key::L,KeyRepeat::No) {
+                if let Some((mouse_x,mouse_y)) = app.window.get_mouse_pos(MouseMode::Discard) {
+                    lights.push(PointLight {
+                        position: into_cell(mouse_x,mouse_y),
+                        color: LIGHT_COLORS[lights.len() % LIGHT_COLORS.len()],
+                        radius: unsafe { HEIGHT as f32 / 3. },
+                        falloff: 1.5,
+                    });
+                }
+            }
+            if app.window.is_key_pressed(Key::C,KeyRepeat::No) {
+                lights.clear();
+            }
+        }
         clear_screen();
     }
+
+    if let Some(path) = &map_path {
+        let file = std::fs::File::create(path).unwrap_or_else(|err| {
+            panic!("|| couldn't save the map {} ({})||",path,err);
+        });
+        let mut writer = std::io::BufWriter::new(file);
+        quadtree.write_to(&mut writer).unwrap_or_else(|err| {
+            panic!("|| couldn't save the map {} ({})||",path,err);
+        });
+    }
 }
 
 
-pub fn dda_quad(start: Vec2, dir: Vec2, max_distance: f32,chunk_data:&Quadtree) -> Option<(IVec2,Vec2)>{
+pub fn dda_quad<T: PartialEq + Clone>(start: Vec2, dir: Vec2, max_distance: f32,chunk_data:&Quadtree<T>) -> Option<(IVec2,Vec2)>{
     let mut voxel = IVec2::new(
                             start.x.floor() as i32, 
                             start.y.floor() as i32, 
@@ -234,13 +568,18 @@ pub fn dda_quad(start: Vec2, dir: Vec2, max_distance: f32,chunk_data:&Quadtree)
         1. - frac0(x)
     }
 
-    let mut t_max_x = if dir.x > 0. {
+    // a ray parallel to an axis never steps along it (inf * 0 would give NaN)
+    let mut t_max_x = if dir.x == 0. {
+        f32::INFINITY
+    } else if dir.x > 0. {
         t_delta.x * frac1(start.x)
     } else {
         t_delta.x * frac0(start.x)
     };
 
-    let mut t_max_y = if dir.y > 0. {
+    let mut t_max_y = if dir.y == 0. {
+        f32::INFINITY
+    } else if dir.y > 0. {
         t_delta.y * frac1(start.y)
     } else {
         t_delta.y * frac0(start.y)
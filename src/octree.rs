@@ -0,0 +1,140 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::spatial_tree::*;
+
+// the children are numbered with one bit per axis, x is 1, y is 2 and z is 4
+// so the lower four are laid out like q0..q3 of the quadtree and the upper four sit above them
+//
+//   z = 0              z = 1
+// + --- + --- +      + --- + --- +
+// |  2  |  3  |      |  6  |  7  |
+// + --- + --- +      + --- + --- +
+// |  0  |  1  |      |  4  |  5  |
+// + --- + --- +      + --- + --- +
+
+// the 3D spatial tree
+pub type OctreeNode<T> = SpatialNode<T, 3>;
+pub type Octree<T> = SpatialTree<T, 3>;
+
+impl<T> OctreeNode<T> {
+    pub fn pos(&self) -> IVec3 {
+        IVec3::new(self.position[0],self.position[1],self.position[2])
+    }
+}
+
+pub struct OctreeRayHit<'a, T> {
+    pub node: &'a OctreeNode<T>,
+    pub value: &'a T,
+    // the t at which the ray enters the node, 0 if the ray starts inside of it
+    pub t_enter: f32,
+    // the t at which the ray leaves the node
+    pub t_exit: f32,
+    pub point: Vec3,
+    // unit normal of the entered face pointing out of the node, if the ray starts inside the
+    // node it is the face it would have entered through
+    pub normal: Vec3,
+}
+
+// returns the first non empty leaf hit by the ray
+pub fn raycast2<'a, T: PartialEq + Clone>(start: Vec3, dir: Vec3, tree: &'a Octree<T>) -> Option<OctreeRayHit<'a,T>> {
+    let hit = tree.raycast_segment([start.x,start.y,start.z],[dir.x,dir.y,dir.z],0.,f32::INFINITY)?;
+    let sign = if hit.upper { 1. } else { -1. };
+    Some(OctreeRayHit {
+        node: hit.node,
+        value: hit.value,
+        t_enter: hit.t_enter,
+        t_exit: hit.t_exit,
+        point: start + dir * hit.t_enter,
+        normal: Vec3::new(
+            if hit.axis == 0 { sign } else { 0. },
+            if hit.axis == 1 { sign } else { 0. },
+            if hit.axis == 2 { sign } else { 0. },
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the coverage of the box of cells from min (inclusive) to max (exclusive)
+    fn box_coverage(min: [i32; 3], max: [i32; 3], pos: [i32; 3], size: i32) -> Coverage {
+        if (0..3).any(|axis| pos[axis] >= max[axis] || pos[axis] + size <= min[axis]) {
+            return Coverage::Outside;
+        }
+        if (0..3).all(|axis| pos[axis] >= min[axis] && pos[axis] + size <= max[axis]) {
+            return Coverage::Inside;
+        }
+        Coverage::Partial
+    }
+
+    #[test]
+    fn set_then_query() {
+        let mut tree: Octree<u8> = Octree::new(8,IVec3::new(0,0,0));
+        tree.set(IVec3::new(1,2,3),1);
+        tree.set(IVec3::new(6,6,6),2);
+        assert_eq!(tree.get(IVec3::new(1,2,3)),Some(&1));
+        assert_eq!(tree.get(IVec3::new(6,6,6)),Some(&2));
+        assert_eq!(tree.get(IVec3::new(3,2,1)),None);
+        // outside of the root
+        assert_eq!(tree.get(IVec3::new(8,0,0)),None);
+
+        let solid: Vec<_> = tree.query(|pos,size| box_coverage([0,0,0],[4,4,4],pos,size))
+            .filter(|node| node.is_full()).map(|node| node.pos()).collect();
+        assert_eq!(solid,vec![IVec3::new(1,2,3)]);
+        assert!(tree.any_solid_in(|pos,size| box_coverage([5,5,5],[8,8,8],pos,size)));
+        assert!(!tree.any_solid_in(|pos,size| box_coverage([0,4,0],[4,8,8],pos,size)));
+
+        tree.remove_block(IVec3::new(1,2,3));
+        assert_eq!(tree.get(IVec3::new(1,2,3)),None);
+        assert!(!tree.any_solid_in(|pos,size| box_coverage([0,0,0],[4,4,4],pos,size)));
+    }
+
+    #[test]
+    fn equal_siblings_merge() {
+        let mut tree: Octree<u8> = Octree::new(8,IVec3::new(0,0,0));
+        for i in 0..8 {
+            tree.set(IVec3::new(4 + (i & 1),4 + (i >> 1 & 1),4 + (i >> 2)),1);
+        }
+        // the eight cells are one leaf of size 2
+        let leaf = tree.leaf_at(IVec3::new(5,5,5)).unwrap();
+        assert_eq!((tree.node(leaf).pos(),tree.node(leaf).size),(IVec3::new(4,4,4),2));
+        assert!(tree.is_canonical(HEAD));
+
+        // a different value splits it again
+        tree.set(IVec3::new(4,4,4),2);
+        assert_eq!(tree.size_at(IVec3::new(5,5,5)),1);
+        tree.set(IVec3::new(4,4,4),1);
+        assert_eq!(tree.size_at(IVec3::new(5,5,5)),2);
+
+        // emptying every cell leaves the root as a single empty leaf
+        for i in 0..8 {
+            tree.remove_block(IVec3::new(4 + (i & 1),4 + (i >> 1 & 1),4 + (i >> 2)));
+        }
+        assert!(tree.head().is_leaf() && !tree.head().is_full());
+    }
+
+    #[test]
+    fn ray_hits_the_entered_face() {
+        let mut tree: Octree<u8> = Octree::new(8,IVec3::new(0,0,0));
+        tree.set(IVec3::new(5,2,3),1);
+
+        let hit = raycast2(Vec3::new(0.5,2.5,3.5),Vec3::new(1.,0.,0.),&tree).unwrap();
+        assert_eq!(hit.node.pos(),IVec3::new(5,2,3));
+        assert_eq!((hit.t_enter,hit.t_exit),(4.5,5.5));
+        assert_eq!((hit.normal.x,hit.normal.y,hit.normal.z),(-1.,0.,0.));
+        assert_eq!((hit.point.x,hit.point.y,hit.point.z),(5.,2.5,3.5));
+
+        // from above through the upper z face
+        let hit = raycast2(Vec3::new(5.5,2.5,7.5),Vec3::new(0.,0.,-1.),&tree).unwrap();
+        assert_eq!(hit.t_enter,3.5);
+        assert_eq!((hit.normal.x,hit.normal.y,hit.normal.z),(0.,0.,1.));
+
+        // diagonally from outside of the root through the lower y face
+        let hit = raycast2(Vec3::new(4.,-2.,2.),Vec3::new(0.25,1.,0.3),&tree).unwrap();
+        assert_eq!(hit.t_enter,4.);
+        assert_eq!((hit.normal.x,hit.normal.y,hit.normal.z),(0.,-1.,0.));
+
+        assert!(raycast2(Vec3::new(0.5,0.5,0.5),Vec3::new(0.,1.,0.),&tree).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+#![allow(dead_code)]
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+
+// an entry of the open list, ordered so the heap pops the lowest estimate first
+struct Open {
+    estimate: f32,
+    leaf: u32,
+}
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate.total_cmp(&other.estimate) == Ordering::Equal
+    }
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+fn distance(a: Vec2, b: Vec2) -> f32 {
+    let d = a - b;
+    (d.x * d.x + d.y * d.y).sqrt()
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // the middle of the edge shared by the node and a neighbor on the side
+    fn portal(&self, id: u32, neighbor: u32, side: Side) -> Vec2 {
+        let a = &self.nodes[id as usize];
+        let b = &self.nodes[neighbor as usize];
+        let (a_pos,b_pos) = (a.pos(),b.pos());
+        match side {
+            Side::XMin | Side::XMax => {
+                let x = if side == Side::XMin { a_pos.x } else { a_pos.x + a.size };
+                let lo = a_pos.y.max(b_pos.y);
+                let hi = (a_pos.y + a.size).min(b_pos.y + b.size);
+                Vec2::new(x as f32, (lo + hi) as f32 / 2.)
+            }
+            Side::YMin | Side::YMax => {
+                let y = if side == Side::YMin { a_pos.y } else { a_pos.y + a.size };
+                let lo = a_pos.x.max(b_pos.x);
+                let hi = (a_pos.x + a.size).min(b_pos.x + b.size);
+                Vec2::new((lo + hi) as f32 / 2., y as f32)
+            }
+        }
+    }
+    // a* over the empty leaves, a leaf is entered through the middle of the edge it shares with
+    // the previous one so the path is made of the start, those portals and the goal
+    // returns None if the start or goal is solid or outside of the tree or if they aren't connected
+    pub fn find_path(&self, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+        let start_leaf = self.leaf_at(ivec2!(start.x.floor() as i32, start.y.floor() as i32))?;
+        let goal_leaf = self.leaf_at(ivec2!(goal.x.floor() as i32, goal.y.floor() as i32))?;
+        if self.nodes[start_leaf as usize].is_full() || self.nodes[goal_leaf as usize].is_full() {
+            return None;
+        }
+
+        // per node of the arena the cost to reach it, the point it was entered at and the leaf
+        // it was entered from
+        let mut cost = vec![f32::INFINITY; self.nodes.len()];
+        let mut point = vec![start; self.nodes.len()];
+        let mut came_from = vec![u32::MAX; self.nodes.len()];
+        let mut closed = vec![false; self.nodes.len()];
+        let mut open = BinaryHeap::new();
+
+        cost[start_leaf as usize] = 0.;
+        open.push(Open { estimate: distance(start,goal), leaf: start_leaf });
+        while let Some(Open { leaf, .. }) = open.pop() {
+            if closed[leaf as usize] {
+                continue;
+            }
+            if leaf == goal_leaf {
+                let mut path = vec![goal];
+                let mut curr = leaf;
+                while curr != start_leaf {
+                    path.push(point[curr as usize]);
+                    curr = came_from[curr as usize];
+                }
+                path.push(start);
+                path.reverse();
+                return Some(path);
+            }
+            closed[leaf as usize] = true;
+
+            for side in [Side::XMin, Side::XMax, Side::YMin, Side::YMax] {
+                for neighbor in self.neighbors(leaf,side) {
+                    if closed[neighbor as usize] || self.nodes[neighbor as usize].is_full() {
+                        continue;
+                    }
+                    let portal = self.portal(leaf,neighbor,side);
+                    let new_cost = cost[leaf as usize] + distance(point[leaf as usize],portal);
+                    if new_cost < cost[neighbor as usize] {
+                        cost[neighbor as usize] = new_cost;
+                        point[neighbor as usize] = portal;
+                        came_from[neighbor as usize] = leaf;
+                        open.push(Open { estimate: new_cost + distance(portal,goal), leaf: neighbor });
+                    }
+                }
+            }
+        }
+        None
+    }
+    // drops the waypoints that can be skipped, from every waypoint it jumps to the furthest one
+    // still in line of sight
+    pub fn smooth_path(&self, path: &[Vec2]) -> Vec<Vec2> {
+        let Some(&first) = path.first() else {
+            return Vec::new();
+        };
+        let mut out = vec![first];
+        let mut curr = 0;
+        while curr + 1 < path.len() {
+            let mut next = curr + 1;
+            for i in (curr + 2..path.len()).rev() {
+                if line_of_sight(path[curr],path[i],self) {
+                    next = i;
+                    break;
+                }
+            }
+            out.push(path[next]);
+            curr = next;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a wall across the tree at x = 8 with a doorway from y = 10 to 12
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(8,0),ivec2!(9,10),1);
+        tree.fill_rect(ivec2!(8,12),ivec2!(9,16),1);
+        tree
+    }
+
+    #[test]
+    fn path_goes_through_the_doorway() {
+        let tree = test_tree();
+        let (start,goal) = (Vec2::new(2.5,2.5),Vec2::new(13.5,2.5));
+        let path = tree.find_path(start,goal).unwrap();
+        assert_eq!((path[0],path[path.len() - 1]),(start,goal));
+        // the wall is only crossed in the doorway
+        for pair in path.windows(2) {
+            if pair[0].x < 8.5 && pair[1].x > 8.5 {
+                let y = pair[0].y + (pair[1].y - pair[0].y) * (8.5 - pair[0].x) / (pair[1].x - pair[0].x);
+                assert!(y > 10. && y < 12.,"crosses the wall at {}",y);
+            }
+        }
+        assert!(path.windows(2).any(|pair| pair[0].x < 8.5 && pair[1].x > 8.5));
+    }
+
+    #[test]
+    fn walled_off_goal_has_no_path() {
+        let mut tree = test_tree();
+        tree.fill_rect(ivec2!(11,1),ivec2!(15,5),1);
+        tree.clear_rect(ivec2!(12,2),ivec2!(14,4));
+        assert!(tree.find_path(Vec2::new(2.5,2.5),Vec2::new(12.5,2.5)).is_none());
+        // nor into or out of a wall
+        assert!(tree.find_path(Vec2::new(2.5,2.5),Vec2::new(8.5,2.5)).is_none());
+        assert!(tree.find_path(Vec2::new(8.5,2.5),Vec2::new(2.5,2.5)).is_none());
+    }
+
+    #[test]
+    fn smoothed_path_is_in_line_of_sight() {
+        let tree = test_tree();
+        let path = tree.find_path(Vec2::new(2.5,2.5),Vec2::new(13.5,2.5)).unwrap();
+        let smooth = tree.smooth_path(&path);
+        assert!(smooth.len() <= path.len());
+        assert_eq!((smooth[0],smooth[smooth.len() - 1]),(path[0],path[path.len() - 1]));
+        for pair in smooth.windows(2) {
+            assert!(line_of_sight(pair[0],pair[1],&tree),"{:?} can't see {:?}",pair[0],pair[1]);
+        }
+        // the doorway isn't on the line from the start to the goal so it takes a turn
+        assert!(smooth.len() >= 3);
+    }
+}
@@ -2,300 +2,500 @@ use my_math::prelude::*;
 use crate::graphics::Framebuffer;
 use crate::CELL_SIZE;
 use crate::from_cell;
+pub use crate::spatial_tree::{Coverage, HEAD};
+use crate::spatial_tree::*;
 
-pub struct QuadtreeNode {
-    pub is_full: bool,
+// the 2D spatial tree, the children are laid out as
+// + ----- + ----- +
+// |  q2   |  q3   |
+// |       |       |
+// + ----- + ----- +
+// |  q0   |  q1   |
+// |       |       |
+// + ----- + ----- +
+pub type QuadtreeNode<T> = SpatialNode<T, 2>;
+pub type Quadtree<T> = SpatialTree<T, 2>;
 
-    pub children: Option<[Box<QuadtreeNode>;4]>,
-    pub size: i32,
-    pub position: IVec2,
+// the sides of a node, min is the side with the smaller coordinate
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Side {
+    XMin,
+    XMax,
+    YMin,
+    YMax,
+}
+impl Side {
+    // the side on the axis, upper is true for the side with the larger coordinate
+    pub fn from_axis(axis: usize, upper: bool) -> Self {
+        match (axis,upper) {
+            (0,false) => Side::XMin,
+            (0,true) => Side::XMax,
+            (_,false) => Side::YMin,
+            (_,true) => Side::YMax,
+        }
+    }
+    // the unit normal pointing out of the node
+    pub fn normal(self) -> Vec2 {
+        match self {
+            Side::XMin => Vec2::new(-1.,  0.),
+            Side::XMax => Vec2::new( 1.,  0.),
+            Side::YMin => Vec2::new( 0., -1.),
+            Side::YMax => Vec2::new( 0.,  1.),
+        }
+    }
+    // the step to the cell on the other side
+    pub fn dir(self) -> [i32; 2] {
+        match self {
+            Side::XMin => [-1,  0],
+            Side::XMax => [ 1,  0],
+            Side::YMin => [ 0, -1],
+            Side::YMax => [ 0,  1],
+        }
+    }
 }
-impl QuadtreeNode {
-    pub fn new(size: i32,pos: IVec2,full: bool) -> Self {
-        QuadtreeNode {
-            is_full: full,
 
-            children: None,
-            size: size,
-            position: pos,
+// the corners of a node named as in the layout above, bottom is the side with the smaller y
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Corner {
+    BottomLeft,
+    BottomRight,
+    TopLeft,
+    TopRight,
+}
+impl Corner {
+    // the diagonal step to the cell across the corner
+    pub fn dir(self) -> [i32; 2] {
+        match self {
+            Corner::BottomLeft  => [-1, -1],
+            Corner::BottomRight => [ 1, -1],
+            Corner::TopLeft     => [-1,  1],
+            Corner::TopRight    => [ 1,  1],
         }
     }
-    pub fn is_leaf(&self) -> bool {
-        self.children.is_none()
+}
+
+impl<T> QuadtreeNode<T> {
+    pub fn pos(&self) -> IVec2 {
+        ivec2!(self.position[0],self.position[1])
     }
-    pub fn remove_block(&mut self, pos: IVec2) {
-        if !self.is_full && self.children.is_none() {
-            // isn't full and a leaf => is empty => nothing to do
-            return;
+}
+impl<T> Quadtree<T> {
+    // the ids of all the leaves touching the side of the node from the outside, smaller leaves
+    // along the side come in the order of the side's axis, empty leaves are included
+    pub fn neighbors(&self, id: u32, side: Side) -> Vec<u32> {
+        let mut out = Vec::new();
+        if let Some(neighbor) = self.neighbor_node(id,side.dir()) {
+            self.leaves_on_side(neighbor,side,&mut out);
         }
-        if self.size == 1 {
-            assert!(self.children.is_none());
-            self.is_full = false;
+        out
+    }
+    // the leaves of the subtree that touch the face looking back toward the side
+    fn leaves_on_side(&self, id: u32, side: Side, out: &mut Vec<u32>) {
+        let Some(first) = self.nodes[id as usize].children else {
+            out.push(id);
             return;
+        };
+        // the children on the half facing back toward the node
+        let children = match side {
+            Side::XMin => [1,3],
+            Side::XMax => [0,2],
+            Side::YMin => [2,3],
+            Side::YMax => [0,1],
+        };
+        for i in children {
+            self.leaves_on_side(first + i,side,out);
         }
-
-        if self.is_full && self.children.is_none() {
-            // is full and a leaf => devide and call recursively on the proper node
-            self.devide(true);
-
-            let children = self.children.as_mut().unwrap();
-
-            let rel_pos = pos - self.position;
-            if rel_pos.x < self.size/2 && rel_pos.y < self.size/2 {
-                children[0].remove_block(pos); 
-            } else if rel_pos.x < self.size && rel_pos.y < self.size/2 {
-                children[1].remove_block(pos); 
-            } else if rel_pos.x < self.size/2 && rel_pos.y < self.size {
-                children[2].remove_block(pos); 
-            } else {
-                children[3].remove_block(pos); 
+    }
+    // the id of the leaf touching the corner of the node from the outside diagonally
+    pub fn neighbor_at_corner(&self, id: u32, corner: Corner) -> Option<u32> {
+        let mut curr = self.neighbor_node(id,corner.dir())?;
+        // the child at the corner facing back toward the node
+        let [dx,dy] = corner.dir();
+        let idx = (dx < 0) as u32 | ((dy < 0) as u32) << 1;
+        while let Some(first) = self.nodes[curr as usize].children {
+            curr = first + idx;
+        }
+        Some(curr)
+    }
+}
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // fills the cells from min (inclusive) to max (exclusive)
+    pub fn fill_rect(&mut self, min: IVec2, max: IVec2, value: T) {
+        self.fill_node(HEAD,&Some(value),&|pos,size| rect_coverage(min,max,ivec2!(pos[0],pos[1]),size));
+    }
+    // empties the cells from min (inclusive) to max (exclusive)
+    pub fn clear_rect(&mut self, min: IVec2, max: IVec2) {
+        self.fill_node(HEAD,&None,&|pos,size| rect_coverage(min,max,ivec2!(pos[0],pos[1]),size));
+        self.shrink();
+    }
+    // fills the cells whose centers are within r of the center
+    pub fn fill_circle(&mut self, center: Vec2, r: f32, value: T) {
+        self.fill_node(HEAD,&Some(value),&|pos,size| circle_coverage(center,r,ivec2!(pos[0],pos[1]),size));
+    }
+    // empties the cells whose centers are within r of the center
+    pub fn clear_circle(&mut self, center: Vec2, r: f32) {
+        self.fill_node(HEAD,&None,&|pos,size| circle_coverage(center,r,ivec2!(pos[0],pos[1]),size));
+        self.shrink();
+    }
+    // fills the cells of the root for which the predicate returns true, the predicate is called
+    // once per cell up front so a leaf is only split if the predicate is true over part of it
+    pub fn fill_mask(&mut self, predicate: impl Fn(IVec2) -> bool, value: T) {
+        let head = self.head();
+        let (root,size) = (head.pos(),head.size as usize);
+        // the number of cells the predicate is true for below and left of every grid point
+        let stride = size + 1;
+        let mut counts = vec![0_u32; stride * stride];
+        for y in 0..size {
+            for x in 0..size {
+                let inside = predicate(ivec2!(root.x + x as i32,root.y + y as i32)) as u32;
+                counts[(y + 1) * stride + x + 1] = inside + counts[y * stride + x + 1] + counts[(y + 1) * stride + x] - counts[y * stride + x];
             }
-            self.is_full = false;
-            return;
         }
-        if let Some(children) = &mut self.children {
-            // is mixed so not full and has children => just call recursively and check if removed
-            // the last child if so merge the four nodes into an empty node
-
-            let rel_pos = pos - self.position;
-            if rel_pos.x < self.size/2 && rel_pos.y < self.size/2 {
-                children[0].remove_block(pos); 
-            } else if rel_pos.x < self.size && rel_pos.y < self.size/2 {
-                children[1].remove_block(pos); 
-            } else if rel_pos.x < self.size/2 && rel_pos.y < self.size {
-                children[2].remove_block(pos); 
+        self.fill_node(HEAD,&Some(value),&|pos,size| {
+            let (x,y,size) = ((pos[0] - root.x) as usize,(pos[1] - root.y) as usize,size as usize);
+            let count = counts[(y + size) * stride + x + size] + counts[y * stride + x]
+                - counts[y * stride + x + size] - counts[(y + size) * stride + x];
+            if count == 0 {
+                Coverage::Outside
+            } else if count as usize == size * size {
+                Coverage::Inside
             } else {
-                children[3].remove_block(pos); 
+                Coverage::Partial
             }
-            // if any child after removing is full return else merge
-            for child in children {
-                if child.is_full || child.children.is_some() {
-                    return;
-                }
+        });
+    }
+    pub fn index_at(&self, pos: IVec2) -> i32 {
+        let Some(id) = self.leaf_at(pos) else {
+            return 1;
+        };
+        if id == HEAD {
+            return -1;
+        }
+        // siblings are stored together so the offset in the group is the child index
+        // (the head is alone at 0 so every group starts at 1 + 4k)
+        return ((id - 1) % 4) as i32;
+    }
+    fn draw_node(&self, id: u32, fb: &mut Framebuffer, color: &impl Fn(&T) -> u32) {
+        let node = &self.nodes[id as usize];
+        if let Some(first) = node.children {
+            for i in 0..4 {
+                self.draw_node(first + i,fb,color);
             }
-            // its already not full so just remove the children and return
-            self.children = None;
-            return;
+        } else if let Some(value) = &node.value {
+            let (x,y) = from_cell(node.pos());
+            unsafe {fb.square(x ,y, node.size * CELL_SIZE , color(value))};
         }
+        let (x,y) = from_cell(node.pos());
+        unsafe {fb.empty_square(x ,y , node.size * CELL_SIZE , !0)};
+    }
+    pub fn draw_outline(&self, fb: &mut Framebuffer, color: impl Fn(&T) -> u32) {
+        self.draw_node(HEAD,fb,&color);
+    }
+    // the leaves overlapping the cells from min (inclusive) to max (exclusive)
+    pub fn query_aabb(&self, min: IVec2, max: IVec2) -> impl Iterator<Item = &QuadtreeNode<T>> {
+        self.query(move |pos,size| rect_coverage(min,max,ivec2!(pos[0],pos[1]),size))
+    }
+    // the leaves holding a cell whose center is within r of the center
+    pub fn query_circle(&self, center: Vec2, r: f32) -> impl Iterator<Item = &QuadtreeNode<T>> {
+        self.query(move |pos,size| circle_coverage(center,r,ivec2!(pos[0],pos[1]),size))
+    }
+    pub fn any_solid_in_aabb(&self, min: IVec2, max: IVec2) -> bool {
+        self.any_solid_in(|pos,size| rect_coverage(min,max,ivec2!(pos[0],pos[1]),size))
+    }
+    pub fn any_solid_in_circle(&self, center: Vec2, r: f32) -> bool {
+        self.any_solid_in(|pos,size| circle_coverage(center,r,ivec2!(pos[0],pos[1]),size))
+    }
+}
 
+fn rect_coverage(min: IVec2, max: IVec2, pos: IVec2, size: i32) -> Coverage {
+    if min.x >= max.x || min.y >= max.y {
+        return Coverage::Outside;
     }
-    pub fn add_block(&mut self, pos: IVec2) {
-        if self.is_full {
-            return;
-        }
-        if self.size == 1 {
-            self.is_full = true;
-            return;
-        } 
-        if self.children.is_none() {
-            // is a leaf and not full so its empty => devide and call recursively
-            assert!(self.children.is_none());
-            self.devide(false);
+    if pos.x >= max.x || pos.x + size <= min.x || pos.y >= max.y || pos.y + size <= min.y {
+        return Coverage::Outside;
+    }
+    if pos.x >= min.x && pos.x + size <= max.x && pos.y >= min.y && pos.y + size <= max.y {
+        return Coverage::Inside;
+    }
+    Coverage::Partial
+}
 
-            let children = self.children.as_mut().unwrap();
+fn circle_coverage(center: Vec2, r: f32, pos: IVec2, size: i32) -> Coverage {
+    // the cell centers of the node span from lo to hi
+    let lo = Vec2::new(pos.x as f32 + 0.5, pos.y as f32 + 0.5);
+    let hi = Vec2::new((pos.x + size) as f32 - 0.5, (pos.y + size) as f32 - 0.5);
 
-            let rel_pos = pos - self.position;
-            if rel_pos.x < self.size/2 && rel_pos.y < self.size/2 {
-                children[0].add_block(pos); 
-            } else if rel_pos.x < self.size && rel_pos.y < self.size/2 {
-                children[1].add_block(pos); 
-            } else if rel_pos.x < self.size/2 && rel_pos.y < self.size {
-                children[2].add_block(pos); 
-            } else {
-                children[3].add_block(pos); 
-            }
-            // is empty so one addition cant make it full
-            return;
-        } else {
-            // isn't a leaf and isn't full => dont devide call recursively and check if filled if
-            // so merge
-            let children = self.children.as_mut().unwrap();
+    // the cell center closest to the center
+    let near_x = ((center.x - 0.5).round() + 0.5).clamp(lo.x,hi.x) - center.x;
+    let near_y = ((center.y - 0.5).round() + 0.5).clamp(lo.y,hi.y) - center.y;
+    if near_x * near_x + near_y * near_y > r * r {
+        return Coverage::Outside;
+    }
+    let far_x = (center.x - lo.x).abs().max((center.x - hi.x).abs());
+    let far_y = (center.y - lo.y).abs().max((center.y - hi.y).abs());
+    if far_x * far_x + far_y * far_y <= r * r {
+        return Coverage::Inside;
+    }
+    Coverage::Partial
+}
 
-            let rel_pos = pos - self.position;
-            if rel_pos.x < self.size/2 && rel_pos.y < self.size/2 {
-                children[0].add_block(pos); 
-            } else if rel_pos.x < self.size && rel_pos.y < self.size/2 {
-                children[1].add_block(pos); 
-            } else if rel_pos.x < self.size/2 && rel_pos.y < self.size {
-                children[2].add_block(pos); 
-            } else {
-                children[3].add_block(pos); 
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            // check if full 
-            for child in children {
-                if !child.is_full {
-                    return;
-                }
-            }
-            self.is_full = true;
-            self.children = None;
-        }
+    // a single full cell at (0,0) splits the root down to it, so along the bottom row there are
+    // leaves of size 1, 2 and 4
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(8,ivec2!(0,0));
+        tree.set(ivec2!(0,0),1);
+        tree
     }
-    pub fn draw_outline(&self, fb: &mut Framebuffer) {
 
-        if let Some(children) = &self.children {
-            for child in children {
-                child.draw_outline(fb);
-            }
-        } else if self.is_full {
-            unsafe {fb.square(from_cell(self.position.x) ,from_cell(self.position.y), self.size * CELL_SIZE , 0)};
-        }
-        unsafe {fb.empty_square(from_cell(self.position.x) ,from_cell(self.position.y) , self.size * CELL_SIZE , !0)};
+    // the lower corner and size of the leaves
+    fn leaves(tree: &Quadtree<u8>, ids: &[u32]) -> Vec<(IVec2,i32)> {
+        ids.iter().map(|id| (tree.node(*id).pos(),tree.node(*id).size)).collect()
     }
-    pub fn devide(&mut self,full: bool) {
-        //self.is_leaf = false;
 
-        let half_size = self.size /2;
-        let pos = self.position;
-        // + ----- + ----- +
-        // |  q2   |  q3   |
-        // |       |       |
-        // + ----- + ----- +
-        // |  q0   |  q1   |
-        // |       |       |
-        // + ----- + ----- +
-        self.children = Some ([ 
-                // q0
-                Box::new(QuadtreeNode::new(half_size,
-                                    pos, full)),                                       
-                // q1
-                Box::new(QuadtreeNode::new(half_size,
-                                    ivec2!(pos.x + half_size , pos.y ),full)),        
-                // q2
-                Box::new(QuadtreeNode::new(half_size,
-                                    ivec2!(pos.x , pos.y + half_size ),full)),         
-                // q3
-                Box::new(QuadtreeNode::new(half_size,
-                                    ivec2!(pos.x + half_size, pos.y + half_size),full)),  
-            ])
+    #[test]
+    fn same_size_neighbor() {
+        let tree = test_tree();
+        let leaf = tree.leaf_at(ivec2!(0,0)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::XMax)),vec![(ivec2!(1,0),1)]);
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::YMax)),vec![(ivec2!(0,1),1)]);
     }
-}
-pub struct Quadtree {
-    pub head: QuadtreeNode
-}
-impl Quadtree {
-    pub fn new(size: i32, pos: IVec2) -> Self {
-        let mut s = size ;
-        while s != 1 {
-            assert!(s % 2 == 0, "the size of the quad tree must be a power of two");
-            s /= 2;
+
+    #[test]
+    fn larger_neighbor() {
+        let tree = test_tree();
+        let leaf = tree.leaf_at(ivec2!(1,0)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::XMax)),vec![(ivec2!(2,0),2)]);
+        let leaf = tree.leaf_at(ivec2!(2,2)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::YMax)),vec![(ivec2!(0,4),4)]);
+    }
+
+    #[test]
+    fn smaller_neighbors() {
+        let tree = test_tree();
+        let leaf = tree.leaf_at(ivec2!(2,0)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::XMin)),vec![(ivec2!(1,0),1),(ivec2!(1,1),1)]);
+        let leaf = tree.leaf_at(ivec2!(4,0)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::XMin)),vec![(ivec2!(2,0),2),(ivec2!(2,2),2)]);
+        let leaf = tree.leaf_at(ivec2!(0,4)).unwrap();
+        assert_eq!(leaves(&tree,&tree.neighbors(leaf,Side::YMin)),vec![(ivec2!(0,2),2),(ivec2!(2,2),2)]);
+    }
+
+    #[test]
+    fn no_neighbor_past_the_root() {
+        let tree = test_tree();
+        let leaf = tree.leaf_at(ivec2!(0,0)).unwrap();
+        assert!(tree.neighbors(leaf,Side::XMin).is_empty());
+        assert!(tree.neighbors(leaf,Side::YMin).is_empty());
+        assert_eq!(tree.neighbor_at_corner(leaf,Corner::BottomLeft),None);
+        assert_eq!(tree.neighbor_at_corner(leaf,Corner::TopLeft),None);
+        let leaf = tree.leaf_at(ivec2!(4,4)).unwrap();
+        assert!(tree.neighbors(leaf,Side::XMax).is_empty());
+        assert_eq!(tree.neighbor_at_corner(leaf,Corner::TopRight),None);
+        assert!(tree.neighbors(HEAD,Side::XMax).is_empty());
+    }
+
+    #[test]
+    fn corner_neighbors() {
+        let tree = test_tree();
+        let leaf = tree.leaf_at(ivec2!(1,1)).unwrap();
+        let corner = tree.neighbor_at_corner(leaf,Corner::TopRight).unwrap();
+        assert_eq!(leaves(&tree,&[corner]),vec![(ivec2!(2,2),2)]);
+        // across the corner of a large leaf the leaf touching it is the smallest one there
+        let leaf = tree.leaf_at(ivec2!(4,4)).unwrap();
+        let corner = tree.neighbor_at_corner(leaf,Corner::BottomLeft).unwrap();
+        assert_eq!(leaves(&tree,&[corner]),vec![(ivec2!(2,2),2)]);
+    }
+
+    // a 32 * 32 tree with leaves from 1 to 16 cells wide, the empty quarter at (16,0) and the full
+    // block at (16,16) are single leaves
+    fn query_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(32,ivec2!(0,0));
+        tree.set(ivec2!(0,0),1);
+        tree.set(ivec2!(3,10),1);
+        tree.fill_rect(ivec2!(16,16),ivec2!(24,24),2);
+        tree.fill_rect(ivec2!(4,20),ivec2!(6,30),1);
+        assert_eq!(tree.node(tree.leaf_at(ivec2!(16,0)).unwrap()).size,16);
+        assert_eq!(tree.node(tree.leaf_at(ivec2!(16,16)).unwrap()).size,8);
+        tree
+    }
+
+    // the coverage of the square worked out from the cells the region holds
+    fn scan_coverage(pos: IVec2, size: i32, inside: &impl Fn(IVec2) -> bool) -> Coverage {
+        let count = (0..size * size).filter(|i| inside(ivec2!(pos.x + i % size,pos.y + i / size))).count() as i32;
+        if count == 0 {
+            Coverage::Outside
+        } else if count == size * size {
+            Coverage::Inside
+        } else {
+            Coverage::Partial
         }
-        Quadtree {
-            head: QuadtreeNode::new(size,pos,false),
+    }
+
+    // checks the coverage of every node, the leaves the query returns and whether a solid cell is
+    // in the region against a scan of the cells
+    fn check_region(tree: &Quadtree<u8>, coverage: impl Fn(IVec2,i32) -> Coverage, query: Vec<&QuadtreeNode<u8>>,
+        any_solid: bool, inside: impl Fn(IVec2) -> bool) {
+        for node in &tree.nodes {
+            assert!(coverage(node.pos(),node.size) == scan_coverage(node.pos(),node.size,&inside),
+                "coverage of {:?} of size {}",node.pos(),node.size);
         }
+        let mut found: Vec<_> = query.iter().map(|leaf| (leaf.pos().x,leaf.pos().y,leaf.size)).collect();
+        let mut expected: Vec<_> = tree.leaves()
+            .filter(|leaf| scan_coverage(leaf.pos(),leaf.size,&inside) != Coverage::Outside)
+            .map(|leaf| (leaf.pos().x,leaf.pos().y,leaf.size)).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found,expected);
+        let solid = (0..32 * 32).any(|i| inside(ivec2!(i % 32,i / 32)) && tree.is_solid_at(ivec2!(i % 32,i / 32)));
+        assert_eq!(any_solid,solid);
     }
-    pub fn new_full(size: i32, pos: IVec2) -> Self {
-        let mut s = size ;
-        while s != 1 {
-            assert!(s % 2 == 0, "the size of the quad tree must be a power of two");
-            s /= 2;
+
+    #[test]
+    fn rect_queries_match_a_cell_scan() {
+        let tree = query_tree();
+        let mut seed = 0x68E31DA4_u32;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        // partly over the large leaves, past the root and empty
+        let mut rects = vec![
+            (ivec2!(18,2),ivec2!(21,9)),
+            (ivec2!(12,12),ivec2!(20,20)),
+            (ivec2!(-4,28),ivec2!(40,40)),
+            (ivec2!(5,5),ivec2!(5,9)),
+        ];
+        for _ in 0..100 {
+            let min = ivec2!((rand() % 40) as i32 - 4,(rand() % 40) as i32 - 4);
+            rects.push((min,ivec2!(min.x + (rand() % 20) as i32,min.y + (rand() % 20) as i32)));
         }
-        Quadtree {
-            head: QuadtreeNode::new(size,pos,true),
+        for (min,max) in rects {
+            let inside = |cell: IVec2| cell.x >= min.x && cell.x < max.x && cell.y >= min.y && cell.y < max.y;
+            check_region(&tree,|pos,size| rect_coverage(min,max,pos,size),tree.query_aabb(min,max).collect(),
+                tree.any_solid_in_aabb(min,max),inside);
         }
     }
-    pub fn add_block(&mut self,pos: IVec2) {
-        if pos.x < self.head.position.x || pos.x >= self.head.position.x + self.head.size ||
-            pos.y < self.head.position.y || pos.y >= self.head.position.y + self.head.size {
-            return;
+
+    #[test]
+    fn circle_queries_match_a_cell_scan() {
+        let tree = query_tree();
+        let mut seed = 0xB5297A4D_u32;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        // partly over the large leaves, around the whole root and between the cell centers
+        let mut circles = vec![
+            (Vec2::new(24.,8.),3.),
+            (Vec2::new(16.,16.),5.5),
+            (Vec2::new(16.,16.),30.),
+            (Vec2::new(10.,10.),0.4),
+        ];
+        for _ in 0..100 {
+            let center = Vec2::new((rand() % 160) as f32 / 4. - 4.,(rand() % 160) as f32 / 4. - 4.);
+            circles.push((center,(rand() % 48) as f32 / 4.));
         }
-        self.head.add_block(pos);
-    }
-    pub fn remove_block(&mut self,pos: IVec2) {
-        if pos.x < self.head.position.x || pos.x >= self.head.position.x + self.head.size ||
-            pos.y < self.head.position.y || pos.y >= self.head.position.y + self.head.size {
-            return;
+        for (center,r) in circles {
+            let inside = |cell: IVec2| {
+                let (dx,dy) = (cell.x as f32 + 0.5 - center.x,cell.y as f32 + 0.5 - center.y);
+                dx * dx + dy * dy <= r * r
+            };
+            check_region(&tree,|pos,size| circle_coverage(center,r,pos,size),tree.query_circle(center,r).collect(),
+                tree.any_solid_in_circle(center,r),inside);
         }
-        self.head.remove_block(pos);
     }
-    pub fn is_solid_at(&self,pos: IVec2) -> bool {
-        if pos.x < self.head.position.x || pos.x >= self.head.position.x + self.head.size ||
-            pos.y < self.head.position.y || pos.y >= self.head.position.y + self.head.size {
-            return false;
-        }
-        let mut curr = &self.head;
-        loop {
-            if curr.is_full {
-                return true;
-            }
-            if curr.children.is_none() || curr.size == 1 {
-                return false;
-            }
 
-            let children = curr.children.as_ref().unwrap();
-            let rel_pos = pos - curr.position;
-            if rel_pos.x < curr.size/2 && rel_pos.y < curr.size/2 {
-                curr = &children[0];
-            } else if rel_pos.x < curr.size && rel_pos.y < curr.size/2 {
-                curr = &children[1];
-            } else if rel_pos.x < curr.size/2 && rel_pos.y < curr.size {
-                curr = &children[2];
-            } else {
-                curr = &children[3];
+    // the cells of the 32 * 32 tree against the expected value of every cell
+    fn check_cells(tree: &Quadtree<u8>, expected: impl Fn(IVec2) -> Option<u8>) {
+        for y in 0..32 {
+            for x in 0..32 {
+                assert_eq!(tree.get(ivec2!(x,y)).copied(),expected(ivec2!(x,y)),"at {:?}",(x,y));
             }
         }
+        assert!(tree.is_canonical(HEAD));
     }
-    pub fn index_at(&self, pos: IVec2) -> i32 {
-        if pos.x < self.head.position.x || pos.x >= self.head.position.x + self.head.size ||
-            pos.y < self.head.position.y || pos.y >= self.head.position.y + self.head.size {
-            return 1;
-        }
-        let mut curr = &self.head;
-        let mut curr_idx = -1;
-        loop {
-            if curr.is_full || curr.children.is_none() {
-                return curr_idx;
-            }
-            if curr.size == 1 {
-                return curr_idx;
-            }
 
-            let children = curr.children.as_ref().unwrap();
-            let rel_pos = pos - curr.position;
-            if rel_pos.x < curr.size/2 && rel_pos.y < curr.size/2 {
-                curr = &children[0];
-                curr_idx = 0;
-            } else if rel_pos.x < curr.size && rel_pos.y < curr.size/2 {
-                curr = &children[1];
-                curr_idx = 1;
-            } else if rel_pos.x < curr.size/2 && rel_pos.y < curr.size {
-                curr = &children[2];
-                curr_idx = 2;
+    #[test]
+    fn fill_and_clear_rects() {
+        let mut tree = query_tree();
+        tree.fill_rect(ivec2!(2,6),ivec2!(20,11),3);
+        tree.clear_rect(ivec2!(14,9),ivec2!(30,22));
+        // past the root only the cells inside it change
+        tree.fill_rect(ivec2!(-5,30),ivec2!(3,40),4);
+        check_cells(&tree,|cell| {
+            let inside = |min: IVec2,max: IVec2| cell.x >= min.x && cell.x < max.x && cell.y >= min.y && cell.y < max.y;
+            if inside(ivec2!(-5,30),ivec2!(3,40)) {
+                Some(4)
+            } else if inside(ivec2!(14,9),ivec2!(30,22)) {
+                None
+            } else if inside(ivec2!(2,6),ivec2!(20,11)) {
+                Some(3)
             } else {
-                curr = &children[3];
-                curr_idx = 3;
+                tree_value(cell)
             }
-        }
+        });
+        // filling the whole root leaves a single leaf
+        tree.fill_rect(ivec2!(0,0),ivec2!(32,32),5);
+        assert!(tree.head().is_leaf() && tree.head().value == Some(5));
     }
-    pub fn size_at(&self, pos: IVec2) -> i32 {
-        if pos.x < self.head.position.x || pos.x >= self.head.position.x + self.head.size ||
-            pos.y < self.head.position.y || pos.y >= self.head.position.y + self.head.size {
-            return 1;
-        }
-        let mut curr = &self.head;
-        loop {
-            if curr.is_full || curr.children.is_none() {
-                return curr.size;
-            }
-            if curr.size == 1 {
-                return 1;
-            }
 
-            let children = curr.children.as_ref().unwrap();
-            let rel_pos = pos - curr.position;
-            if rel_pos.x < curr.size/2 && rel_pos.y < curr.size/2 {
-                curr = &children[0];
-            } else if rel_pos.x < curr.size && rel_pos.y < curr.size/2 {
-                curr = &children[1];
-            } else if rel_pos.x < curr.size/2 && rel_pos.y < curr.size {
-                curr = &children[2];
+    #[test]
+    fn fill_and_clear_circles() {
+        let mut tree = query_tree();
+        let in_circle = |cell: IVec2,center: Vec2,r: f32| {
+            let (dx,dy) = (cell.x as f32 + 0.5 - center.x,cell.y as f32 + 0.5 - center.y);
+            dx * dx + dy * dy <= r * r
+        };
+        tree.fill_circle(Vec2::new(20.,6.),7.5,3);
+        tree.clear_circle(Vec2::new(18.3,17.6),4.2);
+        check_cells(&tree,|cell| {
+            if in_circle(cell,Vec2::new(18.3,17.6),4.2) {
+                None
+            } else if in_circle(cell,Vec2::new(20.,6.),7.5) {
+                Some(3)
             } else {
-                curr = &children[3];
+                tree_value(cell)
             }
-        }
+        });
+    }
+
+    #[test]
+    fn fill_mask_only_splits_mixed_leaves() {
+        let mut tree = query_tree();
+        let calls = std::cell::Cell::new(0);
+        // true over the whole empty quarter at (16,0) and over a diagonal line
+        let mask = |cell: IVec2| (cell.x >= 16 && cell.y < 16) || cell.x == cell.y;
+        tree.fill_mask(|cell| {
+            calls.set(calls.get() + 1);
+            mask(cell)
+        },3);
+        assert_eq!(calls.get(),32 * 32);
+        check_cells(&tree,|cell| if mask(cell) { Some(3) } else { tree_value(cell) });
+        // the quarter is filled as one leaf
+        assert_eq!(tree.node(tree.leaf_at(ivec2!(16,0)).unwrap()).size,16);
+        // a mask that is false everywhere doesn't split anything
+        let nodes = tree.nodes.len();
+        tree.fill_mask(|_| false,4);
+        assert_eq!(tree.nodes.len(),nodes);
     }
-    pub fn draw_outline(&self, fb: &mut Framebuffer) {
-        self.head.draw_outline(fb);
+
+    // the value of the cells of query_tree
+    fn tree_value(cell: IVec2) -> Option<u8> {
+        let inside = |min: IVec2,max: IVec2| cell.x >= min.x && cell.x < max.x && cell.y >= min.y && cell.y < max.y;
+        if inside(ivec2!(16,16),ivec2!(24,24)) {
+            Some(2)
+        } else if cell == ivec2!(0,0) || cell == ivec2!(3,10) || inside(ivec2!(4,20),ivec2!(6,30)) {
+            Some(1)
+        } else {
+            None
+        }
     }
 }
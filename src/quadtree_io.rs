@@ -0,0 +1,267 @@
+#![allow(dead_code)]
+use std::io::{self, Read, Write};
+use crate::quadtree::*;
+
+// + ------------------------------------------------------------ +
+// | magic "QTRE" | version u8 | size i32 | pos.x i32 | pos.y i32 |
+// + ------------------------------------------------------------ +
+// | the nodes in pre-order, 2 bits each, 4 per byte low bits first |
+// + ------------------------------------------------------------ +
+// | the values of the full leaves in the same order               |
+// + ------------------------------------------------------------ +
+// all numbers are little endian
+
+const MAGIC: [u8; 4] = *b"QTRE";
+const VERSION: u8 = 1;
+
+const EMPTY: u8 = 0;
+const FULL: u8 = 1;
+const MIXED: u8 = 2;
+
+// a value that can be stored in a saved quadtree
+pub trait Payload: Sized {
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()>;
+    fn read_from(r: &mut impl Read) -> io::Result<Self>;
+}
+impl Payload for () {
+    fn write_to(&self, _: &mut impl Write) -> io::Result<()> {
+        Ok(())
+    }
+    fn read_from(_: &mut impl Read) -> io::Result<Self> {
+        Ok(())
+    }
+}
+impl Payload for bool {
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&[*self as u8])
+    }
+    fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        match u8::read_from(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(corrupt("bool value isn't 0 or 1")),
+        }
+    }
+}
+macro_rules! int_payload {
+    ($($t:ty),*) => {$(
+        impl Payload for $t {
+            fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+            fn read_from(r: &mut impl Read) -> io::Result<Self> {
+                let mut buf = [0; std::mem::size_of::<$t>()];
+                r.read_exact(&mut buf)?;
+                Ok(<$t>::from_le_bytes(buf))
+            }
+        }
+    )*};
+}
+int_payload!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+fn corrupt(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct BitWriter<'w, W: Write> {
+    w: &'w mut W,
+    byte: u8,
+    len: u8,
+}
+impl<'w, W: Write> BitWriter<'w, W> {
+    fn push(&mut self, code: u8) -> io::Result<()> {
+        self.byte |= code << self.len;
+        self.len += 2;
+        if self.len == 8 {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.len != 0 {
+            self.w.write_all(&[self.byte])?;
+        }
+        self.byte = 0;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+struct BitReader<'r, R: Read> {
+    r: &'r mut R,
+    byte: u8,
+    len: u8,
+}
+impl<'r, R: Read> BitReader<'r, R> {
+    fn next(&mut self) -> io::Result<u8> {
+        if self.len == 0 {
+            self.byte = u8::read_from(self.r)?;
+            self.len = 8;
+        }
+        let code = self.byte & 3;
+        self.byte >>= 2;
+        self.len -= 2;
+        Ok(code)
+    }
+}
+
+impl<T: PartialEq + Clone + Payload> Quadtree<T> {
+    // the data is written a few bytes at a time, wrap files in a BufWriter
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let head = self.head();
+        w.write_all(&MAGIC)?;
+        w.write_all(&[VERSION])?;
+        head.size.write_to(w)?;
+        head.position[0].write_to(w)?;
+        head.position[1].write_to(w)?;
+
+        let mut bits = BitWriter { w: &mut *w, byte: 0, len: 0 };
+        let mut values = Vec::new();
+        fn write_node<'a, T, W: Write>(tree: &'a Quadtree<T>, node: &'a QuadtreeNode<T>, bits: &mut BitWriter<W>, values: &mut Vec<&'a T>) -> io::Result<()> {
+            if let Some(children) = tree.children(node) {
+                bits.push(MIXED)?;
+                for child in children {
+                    write_node(tree,child,bits,values)?;
+                }
+            } else if let Some(value) = &node.value {
+                bits.push(FULL)?;
+                values.push(value);
+            } else {
+                bits.push(EMPTY)?;
+            }
+            Ok(())
+        }
+        write_node(self,head,&mut bits,&mut values)?;
+        bits.flush()?;
+
+        for value in values {
+            value.write_to(w)?;
+        }
+        Ok(())
+    }
+    // returns an InvalidData error on corrupt data and UnexpectedEof on truncated data
+    // the reader is read byte by byte, wrap files in a BufReader
+    pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(corrupt("not a quadtree file"));
+        }
+        let version = u8::read_from(r)?;
+        if version != VERSION {
+            return Err(corrupt("unsupported quadtree file version"));
+        }
+        let size = i32::read_from(r)?;
+        let pos = ivec2!(i32::read_from(r)?, i32::read_from(r)?);
+        if size <= 0 || size & (size - 1) != 0 {
+            return Err(corrupt("the size of the quadtree isn't a power of two"));
+        }
+        if pos.x.checked_add(size).is_none() || pos.y.checked_add(size).is_none() {
+            return Err(corrupt("the quadtree doesn't fit in i32 coordinates"));
+        }
+
+        // the shape is read first with placeholder values, full leaves are remembered in pre-order
+        let mut tree = Quadtree::new(size,pos);
+        let mut bits = BitReader { r: &mut *r, byte: 0, len: 0 };
+        let mut full = Vec::new();
+        fn read_node<T: PartialEq + Clone, R: Read>(tree: &mut Quadtree<T>, id: u32, bits: &mut BitReader<R>, full: &mut Vec<u32>) -> io::Result<()> {
+            match bits.next()? {
+                EMPTY => (),
+                FULL => full.push(id),
+                MIXED => {
+                    if tree.node(id).size == 1 {
+                        return Err(corrupt("a single cell can't have children"));
+                    }
+                    tree.devide(id,None);
+                    let first = tree.node(id).children.unwrap();
+                    for i in 0..4 {
+                        read_node(tree,first + i,bits,full)?;
+                    }
+                }
+                _ => return Err(corrupt("unknown node kind")),
+            }
+            Ok(())
+        }
+        read_node(&mut tree,HEAD,&mut bits,&mut full)?;
+
+        for id in full {
+            tree.nodes[id as usize].value = Some(T::read_from(r)?);
+        }
+        if !tree.is_canonical(HEAD) {
+            return Err(corrupt("a mixed node has four equal leaves as children"));
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(-8,4));
+        tree.fill_rect(ivec2!(-8,4),ivec2!(0,12),1);
+        tree.set(ivec2!(3,13),2);
+        tree.set(ivec2!(7,19),3);
+        tree
+    }
+
+    fn to_bytes(tree: &Quadtree<u8>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<Quadtree<u8>> {
+        Quadtree::read_from(&mut &bytes[..])
+    }
+
+    #[test]
+    fn round_trip() {
+        let tree = test_tree();
+        let read = read(&to_bytes(&tree)).unwrap();
+        assert_eq!((read.head().size,read.head().pos()),(16,ivec2!(-8,4)));
+        let leaves = |tree: &Quadtree<u8>| tree.leaves().map(|leaf| (leaf.pos(),leaf.size,leaf.value)).collect::<Vec<_>>();
+        assert_eq!(leaves(&read),leaves(&tree));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = to_bytes(&test_tree());
+        for len in 0..bytes.len() {
+            let err = read(&bytes[..len]).err().unwrap();
+            assert_eq!(err.kind(),io::ErrorKind::UnexpectedEof, "{} of {} bytes",len,bytes.len());
+        }
+    }
+
+    #[test]
+    fn bad_header_is_an_error() {
+        let bytes = to_bytes(&test_tree());
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(read(&bad_magic).err().unwrap().kind(),io::ErrorKind::InvalidData);
+        let mut bad_version = bytes.clone();
+        bad_version[4] = VERSION + 1;
+        assert_eq!(read(&bad_version).err().unwrap().kind(),io::ErrorKind::InvalidData);
+        let mut bad_size = bytes;
+        bad_size[5] = 3;
+        assert_eq!(read(&bad_size).err().unwrap().kind(),io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn non_canonical_group_is_an_error() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for n in [2_i32,0,0] {
+            bytes.extend(n.to_le_bytes());
+        }
+        // a mixed root whose four children are full leaves of the same value
+        bytes.push(MIXED | FULL << 2 | FULL << 4 | FULL << 6);
+        bytes.push(FULL);
+        bytes.extend([7; 4]);
+        assert_eq!(read(&bytes).err().unwrap().kind(),io::ErrorKind::InvalidData);
+        // with one of them different it's a valid tree
+        *bytes.last_mut().unwrap() = 8;
+        assert_eq!(read(&bytes).unwrap().get(ivec2!(1,1)),Some(&8));
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 use crate::quadtree::*;
+use crate::spatial_tree::{SpatialHit, RayTraversal};
 use my_math::prelude::*;
 
     // + ----- + ----- +
@@ -11,212 +12,162 @@ use my_math::prelude::*;
     // + ----- + ----- +
 
 
-fn next_node(curr_quad: i32, tmx: f32, tmy: f32) -> i32 {
-    const EXIT: i32 = 4;
-    let exit_lookup = [
-        [ 1,    2],
-        [EXIT,  3],
-        [ 3,   EXIT],
-        [EXIT, EXIT]
-    ];
-    return exit_lookup[curr_quad as usize][if tmx <= tmy { 0 } else { 1 }];
+pub struct RayHit<'a, T> {
+    // the id of the node in the tree's arena
+    pub id: u32,
+    pub node: &'a QuadtreeNode<T>,
+    pub value: &'a T,
+    // the t at which the ray enters the node, the start of the ray (or segment) if it starts inside of it
+    pub t_enter: f32,
+    // the t at which the ray leaves the node
+    pub t_exit: f32,
+    pub point: Vec2,
+    // unit normal of the entered side pointing out of the node
+    pub normal: Vec2,
+    // the side the ray entered through, if the ray starts inside the node it is
+    // the side it would have entered through
+    pub side: Side,
 }
-
-fn first_node (tx0: f32, ty0: f32, tmx: f32, tmy: f32) -> i32 {
-    // x entry plane
-    if tx0 > ty0 {
-        if tx0 < tmy {
-            return 0;
-        } else {
-            return 2;
-        }
-    // y entry plane
-    } else {
-        if ty0 < tmx {
-            return 0;
-        } else {
-            return 1;
+impl<'a, T> RayHit<'a, T> {
+    fn new(hit: SpatialHit<'a, T, 2>, start: Vec2, dir: Vec2) -> Self {
+        let side = Side::from_axis(hit.axis,hit.upper);
+        RayHit {
+            id: hit.id,
+            node: hit.node,
+            value: hit.value,
+            t_enter: hit.t_enter,
+            t_exit: hit.t_exit,
+            point: start + dir * hit.t_enter,
+            normal: side.normal(),
+            side,
         }
     }
 }
 
-pub fn raycast<'a>(start: Vec2, dir: Vec2, chunk_data: &'a Quadtree) -> Vec<&'a QuadtreeNode> {
-    //let og_start = start;
-    let mut start = start;
-
-    let node = &chunk_data.head;
-
-    let quad_size = node.size ;
-    let quad_pos = node.position ;
-
-    let mut mask:u8 = 0;
-    if dir.x < 0. {
-        start.x = 2. * quad_pos.x as f32 + node.size as f32 - start.x;
-        mask |= 1;
-    }
-    if dir.y < 0. {
-        start.y = 2. * quad_pos.y as f32 + node.size as f32 - start.y;
-        mask |= 2;
-    }
-
-    let tx0 = (quad_pos.x as f32 - start.x) / dir.x.abs();
-    let ty0 = (quad_pos.y as f32 - start.y) / dir.y.abs();
+// all the leaves hit by the ray in front to back order
+pub fn raycast<'a, T>(start: Vec2, dir: Vec2, chunk_data: &'a Quadtree<T>) -> RayTraversal<'a, T, 2> {
+    RayTraversal::new([start.x,start.y],[dir.x,dir.y],chunk_data)
+}
 
-    let tx1 = (quad_pos.x as f32 + quad_size as f32 - start.x) / dir.x.abs();
-    let ty1 = (quad_pos.y as f32 + quad_size as f32 - start.y) / dir.y.abs();
+// returns the first non empty leaf hit by the ray
+pub fn raycast2<'a, T: PartialEq + Clone>(start: Vec2, dir: Vec2, chunk_data: &'a Quadtree<T>) -> Option<RayHit<'a,T>> {
+    raycast_segment(start,dir,0.,f32::INFINITY,chunk_data)
+}
 
-    let t_min = tx0.max(ty0);
-    let t_max = tx1.min(ty1);
+// true if no non empty leaf lies between a and b, a non empty cell containing b blocks the sight
+pub fn line_of_sight<T: PartialEq + Clone>(a: Vec2, b: Vec2, chunk_data: &Quadtree<T>) -> bool {
+    // a leaf entered right at b only blocks if b is in it, b on its upper edge is in the next cell
+    let cell = [b.x.floor() as i32,b.y.floor() as i32];
+    raycast_segment(a,b - a,0.,1.,chunk_data).is_none_or(|hit| hit.t_enter >= 1. && !hit.node.contains(cell))
+}
 
-    let intersects: bool = t_min < t_max ;
+// returns the first non empty leaf hit by the part of the ray between t_min and t_max
+pub fn raycast_segment<'a, T: PartialEq + Clone>(start: Vec2, dir: Vec2, t_min: f32, t_max: f32, chunk_data: &'a Quadtree<T>) -> Option<RayHit<'a,T>> {
+    let hit = chunk_data.raycast_segment([start.x,start.y],[dir.x,dir.y],t_min,t_max)?;
+    Some(RayHit::new(hit,start,dir))
+}
 
-    if !intersects {
-        println!("no intersection");
-        return Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dda_quad;
+
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(8,4),ivec2!(12,8),1);
+        tree.set(ivec2!(3,12),2);
+        tree.set(ivec2!(12,12),3);
+        tree
     }
 
-    return proc_subtree(start,dir,mask,node,tx0,ty0,tx1,ty1);
+    fn contains(node: &QuadtreeNode<u8>, cell: IVec2) -> bool {
+        cell.x >= node.pos().x && cell.x < node.pos().x + node.size &&
+        cell.y >= node.pos().y && cell.y < node.pos().y + node.size
+    }
 
-    fn proc_subtree(start: Vec2, dir: Vec2, mask: u8,node: &QuadtreeNode,tx0:f32,ty0:f32,tx1:f32,ty1:f32) -> Vec<&QuadtreeNode> {
-        if node.children.is_none() {
-            return vec![node];
-            //if !node.is_full {
-                //return vec![node];
-            //} else {
-                //return Vec::new();
-            //}
+    // compares raycast2 with dda_quad for a ray
+    fn check(tree: &Quadtree<u8>, start: Vec2, dir: Vec2) {
+        let dda = dda_quad(start,dir,100.,tree);
+        let hit = raycast2(start,dir,tree);
+        match (dda,hit) {
+            (None,None) => (),
+            (Some((cell,pos)),Some(hit)) => {
+                assert!(contains(hit.node,cell), "start ({},{}) dir ({},{})",start.x,start.y,dir.x,dir.y);
+                assert!((hit.point.x - pos.x).abs() < 1e-4 && (hit.point.y - pos.y).abs() < 1e-4);
+            }
+            _ => panic!("dda and raycast2 disagree, start ({},{}) dir ({},{})",start.x,start.y,dir.x,dir.y),
         }
+    }
 
-        let txm = (tx0 + tx1) /2.;
-        let tym = (ty0 + ty1) /2.;
-
-        let mut curr_node = first_node(tx0,ty0,txm,tym);
-
-        let children = &node.children.as_ref().unwrap();
-
-        let mut out: Vec<&QuadtreeNode> = Vec::new();
-        while curr_node != 4 {
-            let child = &children[curr_node as usize ^ mask as usize];
-            match curr_node {
-                0 => {
-                    out.extend_from_slice(
-                            &proc_subtree(start,dir,mask,child, tx0,ty0,txm,tym ));
-                    curr_node = next_node(curr_node,txm,tym);
-                },
-                1 => {
-                    out.extend_from_slice(
-                            &proc_subtree(start,dir,mask,child, txm,ty0,tx1,tym ));
-                    curr_node = next_node(curr_node,tx1,tym);
-                },
-                2 => {
-                    out.extend_from_slice(
-                            &proc_subtree(start,dir,mask,child, tx0,tym,txm,ty1));
-                    curr_node = next_node(curr_node,txm,ty1);
-                },
-                3 => {
-                    out.extend_from_slice(
-                            &proc_subtree(start,dir,mask,child, txm,tym,tx1,ty1));
-                    curr_node = 4;
-                },
-                4 => (),
-                _ => panic!(),
+    #[test]
+    fn axis_aligned_rays_match_dda() {
+        let tree = test_tree();
+        let dirs = [Vec2::new(1.,0.),Vec2::new(-1.,0.),Vec2::new(0.,1.),Vec2::new(0.,-1.)];
+        for dir in dirs {
+            for i in 0..32 {
+                for j in 0..32 {
+                    // half cell steps put the origin on grid lines and inside cells
+                    let start = Vec2::new(i as f32 / 2. + 0.25 * dir.x.abs(), j as f32 / 2. + 0.25 * dir.y.abs());
+                    check(&tree,start,dir);
+                }
             }
         }
-        return out;
     }
-}
 
-pub fn raycast2<'a>(start: Vec2, dir: Vec2, chunk_data: &'a Quadtree) -> Option<(&'a QuadtreeNode,f32)> {
-    let mut start = start;
-
-    let node = &chunk_data.head;
-
-    let quad_size = node.size ;
-    let quad_pos = node.position ;
-
-    let mut mask:u8 = 0;
-    if dir.x < 0. {
-        start.x = 2. * quad_pos.x as f32 + node.size as f32 - start.x;
-        mask |= 1;
-    }
-    if dir.y < 0. {
-        start.y = 2. * quad_pos.y as f32 + node.size as f32 - start.y;
-        mask |= 2;
+    #[test]
+    fn ray_along_grid_line() {
+        let tree = test_tree();
+        // y = 4 is the lower edge of the filled rect so the ray runs inside its first row
+        let hit = raycast2(Vec2::new(0.5,4.),Vec2::new(1.,0.),&tree).unwrap();
+        assert_eq!(*hit.value,1);
+        assert_eq!(hit.t_enter,7.5);
+        assert_eq!(hit.side,Side::XMin);
+        assert!(hit.t_enter.is_finite() && hit.t_exit.is_finite());
+
+        // y = 8 is the upper edge so the ray runs just outside of it
+        assert!(raycast2(Vec2::new(0.5,8.),Vec2::new(1.,0.),&tree).is_none());
+
+        let hit = raycast2(Vec2::new(12.,15.5),Vec2::new(0.,-1.),&tree).unwrap();
+        assert_eq!(*hit.value,3);
+        assert_eq!(hit.t_enter,2.5);
+        assert_eq!(hit.side,Side::YMax);
     }
 
-    let tx0 = (quad_pos.x as f32 - start.x) / dir.x.abs();
-    let ty0 = (quad_pos.y as f32 - start.y) / dir.y.abs();
-
-    let tx1 = (quad_pos.x as f32 + quad_size as f32 - start.x) / dir.x.abs();
-    let ty1 = (quad_pos.y as f32 + quad_size as f32 - start.y) / dir.y.abs();
-
-    let t_min = tx0.max(ty0);
-    let t_max = tx1.min(ty1);
-
-    let intersects: bool = t_min < t_max ;
-
-    if !intersects {
-        //println!("no intersection");
-        return None;
+    #[test]
+    fn ray_starting_on_node_boundary() {
+        let tree = test_tree();
+        // starts on the side of the filled rect and moves into it
+        let hit = raycast2(Vec2::new(8.,5.5),Vec2::new(1.,0.),&tree).unwrap();
+        assert_eq!(hit.t_enter,0.);
+        assert_eq!(hit.side,Side::XMin);
+
+        // starts on the side of the filled rect and moves away from it
+        assert!(raycast2(Vec2::new(12.,5.5),Vec2::new(1.,0.),&tree).is_none());
+        assert!(raycast2(Vec2::new(8.,5.5),Vec2::new(-1.,0.),&tree).is_none());
+
+        // starts on the edge of the root
+        let hit = raycast2(Vec2::new(16.,6.),Vec2::new(-1.,0.),&tree).unwrap();
+        assert_eq!(hit.t_enter,4.);
+        assert_eq!(hit.side,Side::XMax);
+        assert!(raycast2(Vec2::new(16.,6.),Vec2::new(1.,0.),&tree).is_none());
     }
 
-    return proc_subtree(start,dir,mask,node,tx0,ty0,tx1,ty1);
-
-    fn proc_subtree(start: Vec2, dir: Vec2, mask: u8,node: &QuadtreeNode,tx0:f32,ty0:f32,tx1:f32,ty1:f32) -> Option<(&QuadtreeNode,f32)> {
-        if !( tx1 >= 0. && ty1 >= 0. ) {
-            return None;
-        }
-        if node.children.is_none() {
-            if node.is_full && ( tx1 >= 0. && ty1 >= 0. ) {
-                if tx0 < 0. && ty0 < 0. {
-                    return Some((node,0.));
-                }else {
-                    let t_min = tx0.max(ty0);
-                    return Some((node,t_min));
-                }
-            } else {
-                return None;
-            }
-        }
+    #[test]
+    fn ray_starting_inside_solid_node() {
+        let tree = test_tree();
+        let hit = raycast2(Vec2::new(9.5,5.5),Vec2::new(0.,1.),&tree).unwrap();
+        assert_eq!(hit.t_enter,0.);
+        assert_eq!(hit.t_exit,2.5);
+        assert_eq!(hit.point.y,5.5);
+    }
 
-        let txm = (tx0 + tx1) /2.;
-        let tym = (ty0 + ty1) /2.;
-
-        let mut curr_node = first_node(tx0,ty0,txm,tym);
-
-        let children = &node.children.as_ref().unwrap();
-
-        while curr_node != 4 {
-            let child = &children[curr_node as usize ^ mask as usize];
-            match curr_node {
-                0 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, child, tx0,ty0,txm,tym ) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,txm,tym);
-                },
-                1 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, child, txm,ty0,tx1,tym ) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,tx1,tym);
-                },
-                2 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, child, tx0,tym,txm,ty1) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,txm,ty1);
-                },
-                3 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, child, txm,tym,tx1,ty1) {
-                        return Some((hit,t));
-                    }
-                    curr_node = 4;
-                },
-                4 => (),
-                _ => panic!(),
-            }
-        }
-        return None;
+    #[test]
+    fn line_of_sight_ends_on_an_edge() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.set(ivec2!(5,0),1);
+        // the lower edge of the solid cell belongs to it, the upper edge to the next cell
+        assert!(!line_of_sight(Vec2::new(0.5,0.5),Vec2::new(5.,0.5),&tree));
+        assert!(line_of_sight(Vec2::new(8.5,0.5),Vec2::new(6.,0.5),&tree));
+        assert!(line_of_sight(Vec2::new(0.5,0.5),Vec2::new(4.9,0.5),&tree));
     }
 }
@@ -0,0 +1,130 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+
+// how far from the wall a reflected ray starts so it doesn't hit the leaf it bounced off again
+const BOUNCE_OFFSET: f32 = 1e-3;
+// the energy below which a ray is too weak to keep bouncing
+const MIN_ENERGY: f32 = 1e-2;
+
+// a straight part of a bouncing ray
+pub struct Segment {
+    pub start: Vec2,
+    pub end: Vec2,
+    // the energy of the ray along the segment, 1 for the first one and multiplied by the
+    // reflectivity of every wall it bounced off
+    pub energy: f32,
+    // the id of the leaf the segment ends on, None if the ray ran out of length first
+    pub hit: Option<u32>,
+}
+
+// the direction reflected about the unit normal
+pub fn reflect(dir: Vec2, normal: Vec2) -> Vec2 {
+    let dot = dir.x * normal.x + dir.y * normal.y;
+    dir - normal * (2. * dot)
+}
+
+// follows the ray from the origin as it bounces off the full leaves until it did max_bounces
+// bounces, traveled max_length, left the tree or hit a wall whose reflectivity (the fraction of
+// the energy it reflects, from 0 to 1) leaves the ray too weak
+// the segments are in the order the ray travels them
+pub fn trace_bounces<T: PartialEq + Clone>(origin: Vec2, dir: Vec2, max_bounces: u32, max_length: f32,
+    reflectivity: impl Fn(&T) -> f32, chunk_data: &Quadtree<T>) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut start = origin;
+    let mut dir = dir.norm();
+    let mut energy = 1.;
+    let mut length = 0.;
+    // the first segment isn't a bounce
+    for _ in 0..=max_bounces {
+        let remaining = max_length - length;
+        let Some(hit) = raycast_segment(start,dir,0.,remaining,chunk_data) else {
+            segments.push(Segment { start, end: start + dir * remaining, energy, hit: None });
+            break;
+        };
+        // dir is a unit vector so t is the distance
+        length += hit.t_enter;
+        segments.push(Segment { start, end: hit.point, energy, hit: Some(hit.id) });
+        // starting inside of a wall there is no side to bounce off
+        if hit.t_enter <= 0. {
+            break;
+        }
+        energy *= reflectivity(hit.value);
+        if energy < MIN_ENERGY {
+            break;
+        }
+        dir = reflect(dir,hit.normal);
+        start = hit.point + hit.normal * BOUNCE_OFFSET;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the unit direction of the segment
+    fn seg_dir(segment: &Segment) -> Vec2 {
+        (segment.end - segment.start).norm()
+    }
+
+    fn distance(a: Vec2, b: Vec2) -> f32 {
+        ((a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)).sqrt()
+    }
+
+    // a room from (1,1) to (15,15) walled all around
+    fn room() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(0,0),ivec2!(16,16),1);
+        tree.clear_rect(ivec2!(1,1),ivec2!(15,15));
+        tree
+    }
+
+    #[test]
+    fn bounces_off_a_wall_at_45_degrees() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(0,12),ivec2!(16,13),1);
+        let segments = trace_bounces(Vec2::new(4.5,8.5),Vec2::new(1.,1.),4,20.,|_| 0.5,&tree);
+        assert_eq!(segments.len(),2);
+        let end = segments[0].end;
+        assert!((end.x - 8.).abs() < 1e-4 && (end.y - 12.).abs() < 1e-4);
+        assert!(segments[0].hit.is_some());
+        // the y part flips and the x part is kept
+        let dir = seg_dir(&segments[1]);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((dir.x - half).abs() < 1e-4 && (dir.y + half).abs() < 1e-4);
+        assert!(segments[1].start.y < 12.);
+        // then it leaves the tree with the rest of its length
+        assert!(segments[1].hit.is_none());
+        let length = distance(segments[0].start,segments[0].end) + distance(segments[1].start,segments[1].end);
+        assert!((length - 20.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn stops_after_max_bounces() {
+        let tree = room();
+        let segments = trace_bounces(Vec2::new(3.5,2.5),Vec2::new(1.,1.),3,1000.,|_| 1.,&tree);
+        // the first segment and one per bounce
+        assert_eq!(segments.len(),4);
+        for pair in segments.windows(2) {
+            let (a,b) = (seg_dir(&pair[0]),seg_dir(&pair[1]));
+            // every bounce flips exactly one of the parts of the diagonal
+            assert!((a.x.abs() - b.x.abs()).abs() < 1e-4 && (a.y.abs() - b.y.abs()).abs() < 1e-4);
+            assert!((a.x.signum() != b.x.signum()) != (a.y.signum() != b.y.signum()));
+            assert!(distance(pair[0].end,pair[1].start) < 1e-2);
+        }
+        assert!(segments.iter().all(|s| s.hit.is_some() && s.energy == 1.));
+    }
+
+    #[test]
+    fn energy_falls_off_by_the_reflectivity() {
+        let tree = room();
+        let segments = trace_bounces(Vec2::new(3.5,2.5),Vec2::new(1.,1.),20,1000.,|_| 0.5,&tree);
+        // the seventh bounce would leave 1 / 128 of the energy, below the minimum
+        assert_eq!(segments.len(),7);
+        for (i,segment) in segments.iter().enumerate() {
+            assert_eq!(segment.energy,0.5_f32.powi(i as i32));
+        }
+    }
+}
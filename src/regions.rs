@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+use std::collections::VecDeque;
+use my_math::prelude::*;
+use crate::quadtree::*;
+
+// a set of leaves connected through their sides
+pub struct Region {
+    pub id: usize,
+    // the number of cells
+    pub area: u64,
+    // the bounding box of the cells, min is inclusive and max exclusive
+    pub min: IVec2,
+    pub max: IVec2,
+    pub leaves: Vec<u32>,
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // the leaves reachable from the start leaf through the sides of leaves accepted by the
+    // predicate, a whole leaf is visited in one step however big it is
+    fn connected_leaves(&self, start: u32, accept: &impl Fn(&Option<T>) -> bool, visited: &mut [bool]) -> Vec<u32> {
+        let mut out = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        visited[start as usize] = true;
+        while let Some(id) = queue.pop_front() {
+            out.push(id);
+            for side in [Side::XMin, Side::XMax, Side::YMin, Side::YMax] {
+                for neighbor in self.neighbors(id,side) {
+                    if !visited[neighbor as usize] && accept(&self.nodes[neighbor as usize].value) {
+                        visited[neighbor as usize] = true;
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+        }
+        out
+    }
+    // the regions of filled (any value) or empty leaves connected through their sides, leaves
+    // touching only at a corner are in different regions
+    pub fn components(&self, filled: bool) -> Vec<Region> {
+        let accept = |value: &Option<T>| value.is_some() == filled;
+        let mut visited = vec![false; self.nodes.len()];
+        let mut regions = Vec::new();
+        for id in self.leaf_ids() {
+            if visited[id as usize] || !accept(&self.nodes[id as usize].value) {
+                continue;
+            }
+            let leaves = self.connected_leaves(id,&accept,&mut visited);
+            let mut area = 0;
+            let mut min = self.nodes[id as usize].pos();
+            let mut max = min;
+            for leaf in &leaves {
+                let node = &self.nodes[*leaf as usize];
+                let pos = node.pos();
+                area += node.size as u64 * node.size as u64;
+                min = ivec2!(min.x.min(pos.x), min.y.min(pos.y));
+                max = ivec2!(max.x.max(pos.x + node.size), max.y.max(pos.y + node.size));
+            }
+            regions.push(Region {
+                id: regions.len(),
+                area,
+                min,
+                max,
+                leaves,
+            });
+        }
+        regions
+    }
+    // sets the value of every leaf connected to the seed through leaves holding the same value as
+    // the seed's leaf, returns the number of cells changed
+    pub fn flood_fill(&mut self, seed: IVec2, value: T) -> u64 {
+        let Some(start) = self.leaf_at(seed) else {
+            return 0;
+        };
+        let old = self.nodes[start as usize].value.clone();
+        if old.as_ref() == Some(&value) {
+            return 0;
+        }
+        let mut visited = vec![false; self.nodes.len()];
+        let leaves = self.connected_leaves(start,&|v| *v == old,&mut visited);
+        let mut area = 0;
+        for id in leaves {
+            let node = &mut self.nodes[id as usize];
+            area += node.size as u64 * node.size as u64;
+            node.value = Some(value.clone());
+        }
+        self.merge_subtree(HEAD);
+        area
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separated_rooms_are_separate_regions() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(0,0),ivec2!(16,16),1);
+        tree.clear_rect(ivec2!(1,1),ivec2!(5,4));
+        tree.clear_rect(ivec2!(8,9),ivec2!(14,15));
+        // only touches the first room at a corner
+        tree.clear_rect(ivec2!(5,4),ivec2!(6,5));
+        let mut rooms: Vec<_> = tree.components(false).iter().map(|r| (r.area,r.min,r.max)).collect();
+        rooms.sort_by_key(|room| room.0);
+        assert_eq!(rooms,vec![
+            (1,ivec2!(5,4),ivec2!(6,5)),
+            (12,ivec2!(1,1),ivec2!(5,4)),
+            (36,ivec2!(8,9),ivec2!(14,15)),
+        ]);
+        // the walls around them are all connected
+        let walls = tree.components(true);
+        assert_eq!(walls.len(),1);
+        assert_eq!((walls[0].area,walls[0].min,walls[0].max),(256 - 49,ivec2!(0,0),ivec2!(16,16)));
+    }
+
+    #[test]
+    fn flood_fill_stops_at_walls() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(8,0),ivec2!(9,16),1);
+        assert_eq!(tree.flood_fill(ivec2!(2,2),2),8 * 16);
+        for y in 0..16 {
+            assert_eq!(tree.get(ivec2!(7,y)),Some(&2));
+            assert_eq!(tree.get(ivec2!(8,y)),Some(&1));
+            assert_eq!(tree.get(ivec2!(9,y)),None);
+        }
+        // filling with the value already there changes nothing
+        assert_eq!(tree.flood_fill(ivec2!(2,2),2),0);
+        // the wall is a region of its own value so it's filled alone
+        assert_eq!(tree.flood_fill(ivec2!(8,3),3),16);
+        assert_eq!(tree.get(ivec2!(7,3)),Some(&2));
+    }
+}
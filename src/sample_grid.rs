@@ -0,0 +1,49 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+
+// a value per sample of a square area of cells, there are resolution * resolution samples per cell
+pub struct SampleGrid<V> {
+    pub resolution: i32,
+    // the number of samples along a side
+    pub size: i32,
+    // the lower corner of the area
+    pub position: IVec2,
+    // row major
+    pub values: Vec<V>,
+}
+impl<V: Copy> SampleGrid<V> {
+    // the grid over the cells of a square of size cells at pos, every sample set to value
+    pub fn new(resolution: i32, pos: IVec2, size: i32, value: V) -> Self {
+        let size = size * resolution;
+        SampleGrid {
+            resolution,
+            size,
+            position: pos,
+            values: vec![value; (size * size) as usize],
+        }
+    }
+    // the world position of the center of a sample
+    pub fn sample_pos(&self, sample: IVec2) -> Vec2 {
+        let step = 1. / self.resolution as f32;
+        Vec2::new(
+            self.position.x as f32 + (sample.x as f32 + 0.5) * step,
+            self.position.y as f32 + (sample.y as f32 + 0.5) * step,
+        )
+    }
+    pub fn at(&self, sample: IVec2) -> V {
+        self.values[(sample.y * self.size + sample.x) as usize]
+    }
+    // the index in values of the sample containing the world position
+    pub fn index(&self, p: Vec2) -> Option<usize> {
+        let x = ((p.x - self.position.x as f32) * self.resolution as f32).floor() as i32;
+        let y = ((p.y - self.position.y as f32) * self.resolution as f32).floor() as i32;
+        if x < 0 || y < 0 || x >= self.size || y >= self.size {
+            return None;
+        }
+        Some((y * self.size + x) as usize)
+    }
+    // the value of the sample containing the world position, None outside of the grid
+    pub fn sample(&self, p: Vec2) -> Option<V> {
+        self.index(p).map(|i| self.values[i])
+    }
+}
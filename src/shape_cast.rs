@@ -0,0 +1,262 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+use crate::quadtree::*;
+
+pub struct ShapeHit<'a, T> {
+    pub node: &'a QuadtreeNode<T>,
+    pub value: &'a T,
+    // the t at which the shape first touches the node, 0 if it starts overlapping it
+    pub t: f32,
+    // the point of the node touched by the shape
+    pub point: Vec2,
+    // unit normal of the node's surface at the contact pointing toward the shape
+    pub normal: Vec2,
+}
+
+// the t interval in which a point moving from o along d is strictly inside the box and the axis
+// of the side it enters through, touching the box isn't being inside of it so a shape resting on
+// a wall can slide along it
+fn box_interval(o: Vec2, d: Vec2, lo: Vec2, hi: Vec2) -> Option<(f32,f32,usize)> {
+    let mut t0 = [0.; 2];
+    let mut t1 = [0.; 2];
+    for (axis,(o,d,lo,hi)) in [(o.x,d.x,lo.x,hi.x),(o.y,d.y,lo.y,hi.y)].into_iter().enumerate() {
+        if d == 0. {
+            if o <= lo || o >= hi {
+                return None;
+            }
+            t0[axis] = f32::NEG_INFINITY;
+            t1[axis] = f32::INFINITY;
+        } else {
+            let ta = (lo - o) / d;
+            let tb = (hi - o) / d;
+            t0[axis] = ta.min(tb);
+            t1[axis] = ta.max(tb);
+        }
+    }
+    let axis = if t0[0] > t0[1] { 0 } else { 1 };
+    let t_enter = t0[axis];
+    let t_exit = t1[0].min(t1[1]);
+    if t_enter >= t_exit {
+        return None;
+    }
+    Some((t_enter,t_exit,axis))
+}
+
+// the bounds of the node grown by the extent on every side
+fn grown<T>(node: &QuadtreeNode<T>, extent: Vec2) -> (Vec2,Vec2) {
+    let pos = node.pos();
+    (
+        Vec2::new(pos.x as f32 - extent.x, pos.y as f32 - extent.y),
+        Vec2::new((pos.x + node.size) as f32 + extent.x, (pos.y + node.size) as f32 + extent.y),
+    )
+}
+
+// the normal of the side on the axis facing the center
+fn face_normal<T>(node: &QuadtreeNode<T>, axis: usize, center: Vec2, dir: Vec2) -> Vec2 {
+    let (d,c,mid) = if axis == 0 {
+        (dir.x, center.x, node.pos().x as f32 + node.size as f32 / 2.)
+    } else {
+        (dir.y, center.y, node.pos().y as f32 + node.size as f32 / 2.)
+    };
+    // a shape moving toward the side hits it from the other side, a still one is pushed out the
+    // way it already is
+    let sign = if d > 0. { -1. } else if d < 0. { 1. } else if c < mid { -1. } else { 1. };
+    if axis == 0 { Vec2::new(sign, 0.) } else { Vec2::new(0., sign) }
+}
+
+// the point of the node closest to p
+fn closest_point<T>(node: &QuadtreeNode<T>, p: Vec2) -> Vec2 {
+    let pos = node.pos();
+    Vec2::new(
+        p.x.clamp(pos.x as f32,(pos.x + node.size) as f32),
+        p.y.clamp(pos.y as f32,(pos.y + node.size) as f32),
+    )
+}
+
+// the center of the shape moving from origin along dir up to max_t
+#[derive(Clone, Copy)]
+struct Sweep {
+    origin: Vec2,
+    dir: Vec2,
+    // the half size of the box around the shape
+    extent: Vec2,
+    max_t: f32,
+}
+
+// finds the first full leaf the shape touches, the nodes are visited through their bounds grown by
+// the extent of the shape in order of the t at which the center enters them, subtrees entered after
+// the best hit so far or after max_t are skipped
+// grown siblings overlap so a child entered later can still be touched first, which is why this is
+// a best-first search instead of the parametric traversal
+// leaf_hit returns the t and normal of the contact with a full leaf
+fn cast<'a, T: PartialEq + Clone>(tree: &'a Quadtree<T>, id: u32, sweep: &Sweep,
+    leaf_hit: &impl Fn(&QuadtreeNode<T>,f32,f32,usize) -> Option<(f32,Vec2)>, best: &mut Option<ShapeHit<'a,T>>) {
+    let Sweep { origin, dir, extent, max_t } = *sweep;
+    let node = &tree.nodes[id as usize];
+    let (lo,hi) = grown(node,extent);
+    let Some((t_enter,t_exit,mut axis)) = box_interval(origin,dir,lo,hi) else {
+        return;
+    };
+    let best_t = best.as_ref().map_or(max_t,|hit| hit.t);
+    if t_exit <= 0. || t_enter.max(0.) > best_t {
+        return;
+    }
+    let Some(first) = node.children else {
+        let Some(value) = &node.value else {
+            return;
+        };
+        // a shape starting inside is pushed out along the axis it overlaps the least
+        if t_enter <= 0. {
+            let depth = |o: f32,lo: f32,hi: f32| (o - lo).min(hi - o);
+            axis = if depth(origin.x,lo.x,hi.x) < depth(origin.y,lo.y,hi.y) { 0 } else { 1 };
+        }
+        if let Some((t,normal)) = leaf_hit(node,t_enter,t_exit,axis) {
+            if t <= best_t {
+                let center = origin + dir * t;
+                *best = Some(ShapeHit { node, value, t, point: closest_point(node,center), normal });
+            }
+        }
+        return;
+    };
+    let mut children = [(0_f32,0_u32); 4];
+    let mut count = 0;
+    for i in 0..4 {
+        let (lo,hi) = grown(&tree.nodes[(first + i) as usize],extent);
+        if let Some((t_enter,_,_)) = box_interval(origin,dir,lo,hi) {
+            children[count] = (t_enter,first + i);
+            count += 1;
+        }
+    }
+    children[..count].sort_by(|a,b| a.0.total_cmp(&b.0));
+    for (_,child) in &children[..count] {
+        cast(tree,*child,sweep,leaf_hit,best);
+    }
+}
+
+// sweeps an axis aligned box with the half extents from the origin along dir and returns the first
+// full leaf it touches before max_t
+pub fn shape_cast_aabb<'a, T: PartialEq + Clone>(origin: Vec2, dir: Vec2, half_extents: Vec2, max_t: f32, tree: &'a Quadtree<T>) -> Option<ShapeHit<'a,T>> {
+    let mut best = None;
+    let sweep = Sweep { origin, dir, extent: half_extents, max_t };
+    cast(tree,HEAD,&sweep,&|node,t_enter,_,axis| {
+        let t = t_enter.max(0.);
+        Some((t,face_normal(node,axis,origin + dir * t,dir)))
+    },&mut best);
+    best
+}
+
+// sweeps a circle with the radius from the origin along dir and returns the first full leaf it
+// touches before max_t
+pub fn shape_cast_circle<'a, T: PartialEq + Clone>(origin: Vec2, dir: Vec2, radius: f32, max_t: f32, tree: &'a Quadtree<T>) -> Option<ShapeHit<'a,T>> {
+    let mut best = None;
+    let sweep = Sweep { origin, dir, extent: Vec2::new(radius,radius), max_t };
+    cast(tree,HEAD,&sweep,&|node,t_enter,t_exit,axis| {
+        // the grown square is the node grown by the circle except around the corners where the
+        // center has to come within the radius of the corner itself
+        let t = t_enter.max(0.);
+        let center = origin + dir * t;
+        let pos = node.pos();
+        let (lo,hi) = (Vec2::new(pos.x as f32,pos.y as f32),Vec2::new((pos.x + node.size) as f32,(pos.y + node.size) as f32));
+        let in_x = center.x >= lo.x && center.x <= hi.x;
+        let in_y = center.y >= lo.y && center.y <= hi.y;
+        if in_x || in_y {
+            return Some((t,face_normal(node,axis,center,dir)));
+        }
+        // the corner region, to get past the corner into the sides the center would have to
+        // enter its circle first
+        let corner = closest_point(node,center);
+        let from_corner = origin - corner;
+        let a = dir.x * dir.x + dir.y * dir.y;
+        let b = 2. * (from_corner.x * dir.x + from_corner.y * dir.y);
+        let c = from_corner.x * from_corner.x + from_corner.y * from_corner.y - radius * radius;
+        if c < 0. {
+            // already overlapping the corner
+            let dist = (from_corner.x * from_corner.x + from_corner.y * from_corner.y).sqrt();
+            let normal = if dist == 0. { face_normal(node,axis,center,dir) } else { from_corner * (1. / dist) };
+            return Some((0.,normal));
+        }
+        let disc = b * b - 4. * a * c;
+        if a == 0. || disc < 0. {
+            return None;
+        }
+        let t = (-b - disc.sqrt()) / (2. * a);
+        if t < 0. || t >= t_exit {
+            return None;
+        }
+        let offset = origin + dir * t - corner;
+        Some((t,offset * (1. / radius)))
+    },&mut best);
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a wall along x = 8 and a single cell at (12,12)
+    fn test_tree() -> Quadtree<u8> {
+        let mut tree = Quadtree::new(16,ivec2!(0,0));
+        tree.fill_rect(ivec2!(8,0),ivec2!(9,8),1);
+        tree.set(ivec2!(12,12),2);
+        tree
+    }
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4
+    }
+
+    #[test]
+    fn face_hit() {
+        let tree = test_tree();
+        let half = Vec2::new(0.5,0.5);
+        let aabb = shape_cast_aabb(Vec2::new(2.5,4.5),Vec2::new(1.,0.),half,100.,&tree).unwrap();
+        let circle = shape_cast_circle(Vec2::new(2.5,4.5),Vec2::new(1.,0.),0.5,100.,&tree).unwrap();
+        for (t,point,normal) in [(aabb.t,aabb.point,aabb.normal),(circle.t,circle.point,circle.normal)] {
+            assert!((t - 5.).abs() < 1e-4);
+            assert!(close(point,Vec2::new(8.,4.5)));
+            assert!(close(normal,Vec2::new(-1.,0.)));
+        }
+    }
+
+    #[test]
+    fn corner_hit() {
+        let tree = test_tree();
+        // the path passes the lower right corner of (12,12) closer than the half size of the box
+        // along the axes but further than the radius of the circle from the corner itself
+        let (origin,dir) = (Vec2::new(10.,8.15),Vec2::new(1.,1.));
+        let aabb = shape_cast_aabb(origin,dir,Vec2::new(0.5,0.5),100.,&tree).unwrap();
+        assert_eq!(*aabb.value,2);
+        assert!((aabb.t - 3.35).abs() < 1e-4);
+        assert!(close(aabb.normal,Vec2::new(0.,-1.)));
+        assert!(shape_cast_circle(origin,dir,0.5,100.,&tree).is_none());
+
+        // closer to the corner the circle touches it with the normal pointing from the corner
+        let circle = shape_cast_circle(Vec2::new(10.,8.5),dir,0.5,100.,&tree).unwrap();
+        let from_corner = Vec2::new(10.,8.5) + dir * circle.t - Vec2::new(13.,12.);
+        assert!(((from_corner.x * from_corner.x + from_corner.y * from_corner.y).sqrt() - 0.5).abs() < 1e-4);
+        assert!(close(circle.point,Vec2::new(13.,12.)));
+        assert!(close(circle.normal,from_corner * 2.));
+    }
+
+    #[test]
+    fn starting_in_contact() {
+        let tree = test_tree();
+        let aabb = shape_cast_aabb(Vec2::new(7.8,4.5),Vec2::new(-1.,0.),Vec2::new(0.5,0.5),100.,&tree).unwrap();
+        assert_eq!(aabb.t,0.);
+        let circle = shape_cast_circle(Vec2::new(7.8,4.5),Vec2::new(0.,1.),0.5,100.,&tree).unwrap();
+        assert_eq!(circle.t,0.);
+        assert!(close(circle.normal,Vec2::new(-1.,0.)));
+        // resting against the wall isn't touching it so the shape can slide along it
+        assert!(shape_cast_aabb(Vec2::new(7.5,4.5),Vec2::new(0.,1.),Vec2::new(0.5,0.5),2.,&tree).is_none());
+    }
+
+    #[test]
+    fn max_t_cuts_the_sweep() {
+        let tree = test_tree();
+        let (origin,dir) = (Vec2::new(2.5,4.5),Vec2::new(1.,0.));
+        assert!(shape_cast_aabb(origin,dir,Vec2::new(0.5,0.5),4.9,&tree).is_none());
+        assert!(shape_cast_circle(origin,dir,0.5,4.9,&tree).is_none());
+        assert!(shape_cast_aabb(origin,dir,Vec2::new(0.5,0.5),5.1,&tree).is_some());
+        assert!(shape_cast_circle(origin,dir,0.5,5.1,&tree).is_some());
+    }
+}
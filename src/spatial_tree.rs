@@ -0,0 +1,877 @@
+#![allow(dead_code)]
+use my_math::prelude::*;
+
+// a 2^D tree, D = 2 is the quadtree and D = 3 the octree
+//
+// the children of a node are numbered with one bit per axis, the bit of axis a is set when the
+// child is in the upper half along it (x is 1, y is 2, z is 4)
+
+// a cell position in D dimensions
+pub trait Coords<const D: usize>: Copy {
+    fn to_array(self) -> [i32; D];
+}
+impl<const D: usize> Coords<D> for [i32; D] {
+    fn to_array(self) -> [i32; D] {
+        self
+    }
+}
+impl Coords<2> for IVec2 {
+    fn to_array(self) -> [i32; 2] {
+        [self.x, self.y]
+    }
+}
+impl Coords<3> for IVec3 {
+    fn to_array(self) -> [i32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+// how much of a node a region covers
+#[derive(Clone, Copy, PartialEq)]
+pub enum Coverage {
+    Outside,
+    Inside,
+    Partial,
+}
+
+pub struct SpatialNode<T, const D: usize> {
+    // the payload of a leaf, `None` means the leaf is empty
+    // nodes with children never hold a value
+    pub value: Option<T>,
+
+    // index of the first child in the arena, the 2^D children are stored next to each other
+    pub children: Option<u32>,
+    pub size: i32,
+    pub position: [i32; D],
+}
+impl<T, const D: usize> SpatialNode<T, D> {
+    pub fn new(size: i32,pos: impl Coords<D>,value: Option<T>) -> Self {
+        SpatialNode {
+            value,
+
+            children: None,
+            size,
+            position: pos.to_array(),
+        }
+    }
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_none()
+    }
+    pub fn is_full(&self) -> bool {
+        self.value.is_some()
+    }
+    pub fn contains(&self, pos: [i32; D]) -> bool {
+        (0..D).all(|axis| pos[axis] >= self.position[axis] && pos[axis] < self.position[axis] + self.size)
+    }
+    // the index of the child containing the position
+    pub fn child_index(&self, pos: [i32; D]) -> u32 {
+        let half = self.size / 2;
+        let mut idx = 0;
+        for (axis,p) in pos.iter().enumerate() {
+            idx |= ((p - self.position[axis] >= half) as u32) << axis;
+        }
+        idx
+    }
+}
+
+// the index of the root in the arena
+pub const HEAD: u32 = 0;
+
+pub struct SpatialTree<T, const D: usize> {
+    // all the nodes of the tree, the head is the first one
+    pub nodes: Vec<SpatialNode<T, D>>,
+    // the first index of every group of children freed by a merge, reused by devide
+    free: Vec<u32>,
+    // set cells outside of the root grow the tree instead of being ignored
+    growable: bool,
+    // the size and position a growable tree shrinks back to
+    min_size: i32,
+    min_position: [i32; D],
+}
+impl<T, const D: usize> SpatialTree<T, D> {
+    // the number of children of a node
+    pub const CHILDREN: usize = 1 << D;
+
+    pub fn head(&self) -> &SpatialNode<T, D> {
+        &self.nodes[HEAD as usize]
+    }
+    pub fn node(&self, id: u32) -> &SpatialNode<T, D> {
+        &self.nodes[id as usize]
+    }
+    // the children of the node ordered by their index
+    pub fn children(&self, node: &SpatialNode<T, D>) -> Option<&[SpatialNode<T, D>]> {
+        node.children.map(|first| &self.nodes[first as usize..first as usize + Self::CHILDREN])
+    }
+    // all the leaves of the tree in pre-order, empty ones included
+    pub fn leaves(&self) -> impl Iterator<Item = &SpatialNode<T, D>> {
+        self.leaf_ids().map(|id| &self.nodes[id as usize])
+    }
+    // the ids of the leaves in the same order
+    pub fn leaf_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut stack = vec![HEAD];
+        std::iter::from_fn(move || {
+            while let Some(id) = stack.pop() {
+                match self.nodes[id as usize].children {
+                    Some(first) => stack.extend((0..Self::CHILDREN as u32).rev().map(|i| first + i)),
+                    None => return Some(id),
+                }
+            }
+            None
+        })
+    }
+    // the leaves overlapping a region in pre-order, empty ones included, the subtrees outside of
+    // the region are skipped
+    pub fn query(&self, cover: impl Fn([i32; D],i32) -> Coverage) -> impl Iterator<Item = &SpatialNode<T, D>> {
+        let mut stack = vec![HEAD];
+        std::iter::from_fn(move || {
+            while let Some(id) = stack.pop() {
+                let node = &self.nodes[id as usize];
+                if cover(node.position,node.size) == Coverage::Outside {
+                    continue;
+                }
+                match node.children {
+                    Some(first) => stack.extend((0..Self::CHILDREN as u32).rev().map(|i| first + i)),
+                    None => return Some(node),
+                }
+            }
+            None
+        })
+    }
+    // true if a full leaf overlaps the region, stops at the first one
+    pub fn any_solid_in(&self, cover: impl Fn([i32; D],i32) -> Coverage) -> bool {
+        self.query(cover).any(|node| node.is_full())
+    }
+}
+impl<T: PartialEq + Clone, const D: usize> SpatialTree<T, D> {
+    pub fn new(size: i32, pos: impl Coords<D>) -> Self {
+        let mut s = size ;
+        while s != 1 {
+            assert!(s % 2 == 0, "the size of the tree must be a power of two");
+            s /= 2;
+        }
+        let pos = pos.to_array();
+        SpatialTree {
+            nodes: vec![SpatialNode::new(size,pos,None)],
+            free: Vec::new(),
+            growable: false,
+            min_size: size,
+            min_position: pos,
+        }
+    }
+    pub fn new_full(size: i32, pos: impl Coords<D>, value: T) -> Self {
+        let mut s = size ;
+        while s != 1 {
+            assert!(s % 2 == 0, "the size of the tree must be a power of two");
+            s /= 2;
+        }
+        let pos = pos.to_array();
+        SpatialTree {
+            nodes: vec![SpatialNode::new(size,pos,Some(value))],
+            free: Vec::new(),
+            growable: false,
+            min_size: size,
+            min_position: pos,
+        }
+    }
+    pub fn devide(&mut self, id: u32, value: Option<T>) {
+        let node = &self.nodes[id as usize];
+        let half_size = node.size /2;
+        let pos = node.position;
+        let children = (0..Self::CHILDREN).map(|i| {
+            let mut child_pos = pos;
+            for (axis,p) in child_pos.iter_mut().enumerate() {
+                *p += (i >> axis & 1) as i32 * half_size;
+            }
+            SpatialNode::new(half_size,child_pos,value.clone())
+        });
+        let first = if let Some(first) = self.free.pop() {
+            for (i,child) in children.enumerate() {
+                self.nodes[first as usize + i] = child;
+            }
+            first
+        } else {
+            let first = self.nodes.len() as u32;
+            self.nodes.extend(children);
+            first
+        };
+        self.nodes[id as usize].children = Some(first);
+    }
+    // gives the children of the node (and all of their descendants) back to the free list
+    fn free_children(&mut self, id: u32) {
+        let Some(first) = self.nodes[id as usize].children.take() else {
+            return;
+        };
+        for i in 0..Self::CHILDREN as u32 {
+            self.free_children(first + i);
+        }
+        self.free.push(first);
+    }
+    // if all the children are leaves holding the same value merge them into the node
+    fn try_merge(&mut self, id: u32) {
+        let first = self.nodes[id as usize].children.unwrap() as usize;
+        for child in &self.nodes[first..first + Self::CHILDREN] {
+            if !child.is_leaf() || child.value != self.nodes[first].value {
+                return;
+            }
+        }
+        self.nodes[id as usize].value = self.nodes[first].value.take();
+        self.nodes[id as usize].children = None;
+        self.free.push(first as u32);
+    }
+    // merges every node of the subtree that can be merged, for when leaf values were changed
+    // directly in the arena
+    pub fn merge_subtree(&mut self, id: u32) {
+        let Some(first) = self.nodes[id as usize].children else {
+            return;
+        };
+        for i in 0..Self::CHILDREN as u32 {
+            self.merge_subtree(first + i);
+        }
+        self.try_merge(id);
+    }
+    // true if no node in the subtree could be merged
+    pub fn is_canonical(&self, id: u32) -> bool {
+        let Some(first) = self.nodes[id as usize].children else {
+            return true;
+        };
+        let children = &self.nodes[first as usize..first as usize + Self::CHILDREN];
+        if children.iter().all(|child| child.is_leaf() && child.value == children[0].value) {
+            return false;
+        }
+        (0..Self::CHILDREN as u32).all(|i| self.is_canonical(first + i))
+    }
+    fn set_node(&mut self, id: u32, pos: [i32; D], value: Option<T>) {
+        let node = &mut self.nodes[id as usize];
+        if node.children.is_none() {
+            if node.value == value {
+                // the leaf already holds the value => nothing to do
+                return;
+            }
+            if node.size == 1 {
+                node.value = value;
+                return;
+            }
+            // is a leaf with a different value => devide and call recursively on the proper node
+            let old = node.value.take();
+            self.devide(id,old);
+        }
+
+        let node = &self.nodes[id as usize];
+        let child = node.children.unwrap() + node.child_index(pos);
+        self.set_node(child,pos,value);
+
+        self.try_merge(id);
+    }
+    // sets every cell covered by the region to the value, whole nodes inside the region are
+    // replaced directly and only the nodes on the region boundary get devided
+    pub fn fill_node(&mut self, id: u32, value: &Option<T>, cover: &impl Fn([i32; D],i32) -> Coverage) {
+        let node = &self.nodes[id as usize];
+        match cover(node.position,node.size) {
+            Coverage::Outside => return,
+            Coverage::Inside => {
+                self.free_children(id);
+                self.nodes[id as usize].value = value.clone();
+                return;
+            }
+            Coverage::Partial => (),
+        }
+        if node.children.is_none() {
+            if node.value == *value {
+                return;
+            }
+            // a single cell can't be partially covered
+            assert!(node.size != 1);
+            let old = self.nodes[id as usize].value.take();
+            self.devide(id,old);
+        }
+
+        let first = self.nodes[id as usize].children.unwrap();
+        for i in 0..Self::CHILDREN as u32 {
+            self.fill_node(first + i,value,cover);
+        }
+
+        self.try_merge(id);
+    }
+    // in a growable tree setting a cell outside of the root wraps the root in bigger parents until
+    // the cell fits and emptying cells shrinks it back, never below the root it had when made growable
+    pub fn set_growable(&mut self, growable: bool) {
+        self.growable = growable;
+        self.min_size = self.head().size;
+        self.min_position = self.head().position;
+    }
+    pub fn is_growable(&self) -> bool {
+        self.growable
+    }
+    // wraps the root in a parent twice its size, the parent extends toward the position on every
+    // axis where the position is below the root, returns false if the parent doesn't fit in i32
+    fn grow(&mut self, pos: [i32; D]) -> bool {
+        let head = self.head();
+        let Some(size) = head.size.checked_mul(2) else {
+            return false;
+        };
+        let mut parent_pos = head.position;
+        // the old root is the child on the side it didn't grow toward
+        let mut idx = 0;
+        for axis in 0..D {
+            if pos[axis] < head.position[axis] {
+                let Some(p) = head.position[axis].checked_sub(head.size) else {
+                    return false;
+                };
+                parent_pos[axis] = p;
+                idx |= 1 << axis;
+            }
+            if parent_pos[axis].checked_add(size).is_none() {
+                return false;
+            }
+        }
+        let old = std::mem::replace(&mut self.nodes[HEAD as usize],SpatialNode::new(size,parent_pos,None));
+        self.devide(HEAD,None);
+        let first = self.nodes[HEAD as usize].children.unwrap();
+        self.nodes[(first + idx) as usize] = old;
+        // an empty root just becomes a bigger empty root
+        self.try_merge(HEAD);
+        true
+    }
+    // replaces the root of a growable tree by its child toward the root it had when made growable
+    // while the other children are empty, an empty root goes straight back to that root
+    pub fn shrink(&mut self) {
+        if !self.growable {
+            return;
+        }
+        while self.head().size > self.min_size {
+            let head = self.head();
+            let Some(first) = head.children else {
+                if head.value.is_none() {
+                    self.nodes[HEAD as usize].size = self.min_size;
+                    self.nodes[HEAD as usize].position = self.min_position;
+                }
+                return;
+            };
+            // the grown roots are made of whole parents so the original root is always inside
+            // one of the children
+            let kept = first + head.child_index(self.min_position);
+            let others_empty = (first..first + Self::CHILDREN as u32).filter(|id| *id != kept).all(|id| {
+                let child = &self.nodes[id as usize];
+                child.is_leaf() && child.value.is_none()
+            });
+            if !others_empty {
+                return;
+            }
+            let child = std::mem::replace(&mut self.nodes[kept as usize],SpatialNode::new(1,[0; D],None));
+            self.nodes[HEAD as usize] = child;
+            self.free.push(first);
+        }
+    }
+    pub fn set(&mut self,pos: impl Coords<D>, value: T) {
+        let pos = pos.to_array();
+        while !self.head().contains(pos) {
+            if !self.growable || !self.grow(pos) {
+                return;
+            }
+        }
+        self.set_node(HEAD,pos,Some(value));
+    }
+    pub fn remove_block(&mut self,pos: impl Coords<D>) {
+        let pos = pos.to_array();
+        if !self.head().contains(pos) {
+            return;
+        }
+        self.set_node(HEAD,pos,None);
+        self.shrink();
+    }
+    // the id of the leaf containing the position
+    pub fn leaf_at(&self,pos: impl Coords<D>) -> Option<u32> {
+        let pos = pos.to_array();
+        if !self.head().contains(pos) {
+            return None;
+        }
+        let mut curr_id = HEAD;
+        loop {
+            let curr = &self.nodes[curr_id as usize];
+            let Some(first) = curr.children else {
+                return Some(curr_id);
+            };
+            curr_id = first + curr.child_index(pos);
+        }
+    }
+    pub fn get(&self,pos: impl Coords<D>) -> Option<&T> {
+        self.leaf_at(pos).and_then(|id| self.nodes[id as usize].value.as_ref())
+    }
+    pub fn is_solid_at(&self,pos: impl Coords<D>) -> bool {
+        self.get(pos).is_some()
+    }
+    pub fn size_at(&self, pos: impl Coords<D>) -> i32 {
+        match self.leaf_at(pos) {
+            Some(id) => self.nodes[id as usize].size,
+            None => 1,
+        }
+    }
+}
+
+impl<T, const D: usize> SpatialTree<T, D> {
+    // the ids of the nodes from the head down to the node, found by descending toward its position
+    pub fn path_to(&self, id: u32) -> Vec<u32> {
+        let pos = self.nodes[id as usize].position;
+        let mut path = vec![HEAD];
+        let mut curr = HEAD;
+        while curr != id {
+            let node = &self.nodes[curr as usize];
+            let first = node.children.expect("the id isn't a node of the tree");
+            curr = first + node.child_index(pos);
+            path.push(curr);
+        }
+        path
+    }
+    // the node of the same size next to the node in the direction (-1, 0 or 1 per axis), or the
+    // leaf covering it if the tree isn't that deep there, None past the edge of the tree
+    //
+    // walks up until the ancestor where the move doesn't leave the parent, mirroring the child
+    // index on the moved axes at every level, then walks back down the mirrored indices
+    pub fn neighbor_node(&self, id: u32, dir: [i32; D]) -> Option<u32> {
+        let path = self.path_to(id);
+        let mut mirrored = Vec::new();
+        // the axes on which the move still leaves the current node
+        let mut carry = 0;
+        for (axis,d) in dir.iter().enumerate() {
+            if *d != 0 {
+                carry |= 1 << axis;
+            }
+        }
+        let mut level = path.len() - 1;
+        while carry != 0 {
+            if level == 0 {
+                return None;
+            }
+            let parent = &self.nodes[path[level - 1] as usize];
+            let idx = path[level] - parent.children.unwrap();
+            let mut next_carry = 0;
+            for (axis,d) in dir.iter().enumerate() {
+                // moving up from the upper half or down from the lower half leaves the parent
+                let upper = idx & (1 << axis) != 0;
+                if carry & (1 << axis) != 0 && upper == (*d > 0) {
+                    next_carry |= 1 << axis;
+                }
+            }
+            mirrored.push(idx ^ carry);
+            carry = next_carry;
+            level -= 1;
+        }
+
+        let mut curr = path[level];
+        while let Some(idx) = mirrored.pop() {
+            let Some(first) = self.nodes[curr as usize].children else {
+                break;
+            };
+            curr = first + idx;
+        }
+        Some(curr)
+    }
+}
+
+// the parametric traversal from "An Efficient Parametric Algorithm for Octree Traversal" for any D
+//
+// the ray is mirrored so every component of the direction is positive, mask has the bits of the
+// mirrored axes so child c of the mirrored space is child c ^ mask of the tree
+// t0, tm and t1 hold the t at which the ray crosses the lower, middle and upper plane of every axis
+
+// the t values at which the ray crosses the lo and hi planes of one axis
+// a ray parallel to the planes is inside the slab for every t if lo <= start < hi (the same rule
+// that assigns a point to a cell) and never otherwise
+pub fn slab_t(start: f32, dir: f32, lo: f32, hi: f32) -> (f32,f32) {
+    if dir == 0. {
+        if start >= lo && start < hi {
+            return (f32::NEG_INFINITY, f32::INFINITY);
+        } else {
+            return (f32::INFINITY, f32::INFINITY);
+        }
+    }
+    ((lo - start) / dir.abs(), (hi - start) / dir.abs())
+}
+
+// the t at which the ray crosses the middle plane of a node, for a parallel ray the middle plane is
+// never crossed so it is put at the infinity that keeps the ray on its side
+pub fn mid_t(t0: f32, t1: f32, start: f32, dir: f32, mid: f32) -> f32 {
+    if dir == 0. {
+        if start < mid {
+            return f32::INFINITY;
+        } else {
+            return f32::NEG_INFINITY;
+        }
+    }
+    (t0 + t1) /2.
+}
+
+// the axis whose lower plane the ray crosses last is the one it enters the node through,
+// ties go to the higher axis
+pub fn entry_axis<const D: usize>(t0: [f32; D]) -> usize {
+    let mut axis = 0;
+    for a in 1..D {
+        if t0[a] >= t0[axis] {
+            axis = a;
+        }
+    }
+    axis
+}
+
+// the first child the ray enters, it's in the upper half of every other axis whose middle plane
+// is crossed before the ray enters the node
+pub fn first_node<const D: usize>(t0: [f32; D], tm: [f32; D]) -> usize {
+    let entry = entry_axis(t0);
+    let mut answer = 0;
+    for (axis,t) in tm.iter().enumerate() {
+        if axis != entry && *t <= t0[entry] {
+            answer |= 1 << axis;
+        }
+    }
+    answer
+}
+
+// the child entered after leaving curr_node, t_exit holds the t of the upper planes of curr_node
+// the ray leaves through the plane it reaches first (ties go to the lower axis) and moves to the
+// neighbour along that axis, if curr_node is already in the upper half the ray leaves the parent
+// so for D = 2 this is the table
+//   [ 1,    2  ],
+//   [EXIT,  3  ],
+//   [ 3,   EXIT],
+//   [EXIT, EXIT],
+pub fn next_node<const D: usize>(curr_node: usize, t_exit: [f32; D]) -> Option<usize> {
+    let mut axis = 0;
+    for a in 1..D {
+        if t_exit[a] < t_exit[axis] {
+            axis = a;
+        }
+    }
+    if curr_node & (1 << axis) != 0 {
+        return None;
+    }
+    Some(curr_node | 1 << axis)
+}
+
+// the t planes of the child of the mirrored space
+pub fn child_t<const D: usize>(child: usize, t0: [f32; D], tm: [f32; D], t1: [f32; D]) -> ([f32; D],[f32; D]) {
+    let mut c0 = t0;
+    let mut c1 = tm;
+    for axis in 0..D {
+        if child & (1 << axis) != 0 {
+            c0[axis] = tm[axis];
+            c1[axis] = t1[axis];
+        }
+    }
+    (c0,c1)
+}
+
+pub fn min_t<const D: usize>(t: [f32; D]) -> f32 {
+    t.iter().fold(f32::INFINITY, |min, t| min.min(*t))
+}
+pub fn max_t<const D: usize>(t: [f32; D]) -> f32 {
+    t.iter().fold(f32::NEG_INFINITY, |max, t| max.max(*t))
+}
+
+// mirrors the ray so every component of the direction is positive and computes the t values of
+// the root planes, returns None if the ray misses the root
+pub fn root_params<const D: usize>(start: [f32; D], dir: [f32; D], pos: [i32; D], size: i32) -> Option<(usize,[f32; D],[f32; D])> {
+    let mut mask = 0;
+    let mut t0 = [0.; D];
+    let mut t1 = [0.; D];
+    for axis in 0..D {
+        let mut start = start[axis];
+        if dir[axis] < 0. {
+            start = 2. * pos[axis] as f32 + size as f32 - start;
+            mask |= 1 << axis;
+        }
+        (t0[axis],t1[axis]) = slab_t(start,dir[axis],pos[axis] as f32,(pos[axis] + size) as f32);
+    }
+    if max_t(t0) >= min_t(t1) {
+        return None;
+    }
+    Some((mask,t0,t1))
+}
+
+// the middle planes of a node
+fn mid_planes<const D: usize>(node: &SpatialNode<impl Sized, D>, start: [f32; D], dir: [f32; D], t0: [f32; D], t1: [f32; D]) -> [f32; D] {
+    let mut tm = [0.; D];
+    for axis in 0..D {
+        tm[axis] = mid_t(t0[axis],t1[axis],start[axis],dir[axis],node.position[axis] as f32 + node.size as f32 /2.);
+    }
+    tm
+}
+
+pub struct SpatialHit<'a, T, const D: usize> {
+    pub id: u32,
+    pub node: &'a SpatialNode<T, D>,
+    pub value: &'a T,
+    // the t at which the ray enters the node, t_min if the ray starts inside of it
+    pub t_enter: f32,
+    // the t at which the ray leaves the node
+    pub t_exit: f32,
+    // the axis of the face the ray entered through, if the ray starts inside the node it is
+    // the face it would have entered through
+    pub axis: usize,
+    // true if the entered face is the upper one along the axis
+    pub upper: bool,
+}
+
+// the part of a ray between t_min and t_max that a traversal looks for leaves on
+#[derive(Clone, Copy)]
+struct Segment<const D: usize> {
+    // the unmirrored origin of the ray, it is only needed for the middle planes of parallel axes
+    // which are never mirrored
+    start: [f32; D],
+    dir: [f32; D],
+    mask: usize,
+    t_min: f32,
+    t_max: f32,
+}
+
+impl<T: PartialEq + Clone, const D: usize> SpatialTree<T, D> {
+    // returns the first non empty leaf hit by the part of the ray between t_min and t_max
+    pub fn raycast_segment(&self, start: [f32; D], dir: [f32; D], t_min: f32, t_max: f32) -> Option<SpatialHit<'_, T, D>> {
+        let head = self.head();
+        let (mask,t0,t1) = root_params(start,dir,head.position,head.size)?;
+        self.proc_subtree(&Segment { start, dir, mask, t_min, t_max },HEAD,t0,t1)
+    }
+    fn proc_subtree(&self, segment: &Segment<D>, id: u32, t0: [f32; D], t1: [f32; D]) -> Option<SpatialHit<'_, T, D>> {
+        let Segment { start, dir, mask, t_min, t_max } = *segment;
+        // the node is before the segment, a segment starting on the far side of a node only touches it at t_min
+        if !t1.iter().all(|t| *t > t_min) {
+            return None;
+        }
+        // the node is past the end of the segment so the whole subtree can be skipped
+        if max_t(t0) > t_max {
+            return None;
+        }
+        let node = &self.nodes[id as usize];
+        let Some(first) = node.children else {
+            let value = node.value.as_ref()?;
+            let axis = entry_axis(t0);
+            return Some(SpatialHit {
+                id,
+                node,
+                value,
+                t_enter: max_t(t0).max(t_min),
+                t_exit: min_t(t1),
+                axis,
+                // in the mirrored space the ray always enters through a lower face
+                upper: mask & (1 << axis) != 0,
+            });
+        };
+
+        let tm = mid_planes(node,start,dir,t0,t1);
+
+        let mut curr_node = Some(first_node(t0,tm));
+        while let Some(curr) = curr_node {
+            let (c0,c1) = child_t(curr,t0,tm,t1);
+            if let Some(hit) = self.proc_subtree(segment,first + (curr ^ mask) as u32,c0,c1) {
+                return Some(hit);
+            }
+            curr_node = next_node(curr,c1);
+        }
+        None
+    }
+}
+
+// the deepest tree the traversal stack can hold, the size of a node is an i32 so a tree can't be
+// deeper than this
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Frame<const D: usize> {
+    id: u32,
+    t0: [f32; D],
+    tm: [f32; D],
+    t1: [f32; D],
+    // the next child to visit in the mirrored space, None when all were visited
+    curr: Option<usize>,
+}
+
+// iterates over every leaf hit by the ray in front to back order, yields the leaf and the t at which
+// the ray enters and leaves it, the part of the ray behind the start is skipped
+pub struct RayTraversal<'a, T, const D: usize> {
+    tree: &'a SpatialTree<T, D>,
+    start: [f32; D],
+    dir: [f32; D],
+    mask: usize,
+    stack: [Frame<D>; MAX_DEPTH],
+    depth: usize,
+    // set when the root itself is a leaf that still has to be yielded
+    root_leaf: Option<(&'a SpatialNode<T, D>,f32,f32)>,
+}
+impl<'a, T, const D: usize> RayTraversal<'a, T, D> {
+    pub fn new(start: [f32; D], dir: [f32; D], tree: &'a SpatialTree<T, D>) -> Self {
+        let empty = Frame { id: HEAD, t0: [0.; D], tm: [0.; D], t1: [0.; D], curr: None };
+        let mut traversal = RayTraversal {
+            tree,
+            start,
+            dir,
+            mask: 0,
+            stack: [empty; MAX_DEPTH],
+            depth: 0,
+            root_leaf: None,
+        };
+        let head = tree.head();
+        if let Some((mask,t0,t1)) = root_params(start,dir,head.position,head.size) {
+            traversal.mask = mask;
+            traversal.root_leaf = traversal.visit(HEAD,t0,t1);
+        }
+        traversal
+    }
+    // yields the node if it's a leaf or pushes it on the stack, nodes behind the ray are skipped
+    fn visit(&mut self, id: u32, t0: [f32; D], t1: [f32; D]) -> Option<(&'a SpatialNode<T, D>,f32,f32)> {
+        if !t1.iter().all(|t| *t > 0.) {
+            return None;
+        }
+        let node = &self.tree.nodes[id as usize];
+        if node.children.is_none() {
+            return Some((node, max_t(t0).max(0.), min_t(t1)));
+        }
+
+        let tm = mid_planes(node,self.start,self.dir,t0,t1);
+        self.stack[self.depth] = Frame {
+            id,
+            t0,
+            tm,
+            t1,
+            curr: Some(first_node(t0,tm)),
+        };
+        self.depth += 1;
+        None
+    }
+}
+impl<'a, T, const D: usize> Iterator for RayTraversal<'a, T, D> {
+    type Item = (&'a SpatialNode<T, D>,f32,f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(leaf) = self.root_leaf.take() {
+            return Some(leaf);
+        }
+        while self.depth != 0 {
+            let frame = &mut self.stack[self.depth - 1];
+            let Some(curr) = frame.curr else {
+                self.depth -= 1;
+                continue;
+            };
+            let (c0,c1) = child_t(curr,frame.t0,frame.tm,frame.t1);
+            frame.curr = next_node(curr,c1);
+
+            let first = self.tree.nodes[frame.id as usize].children.unwrap();
+            let child = first + (curr ^ self.mask) as u32;
+            if let Some(leaf) = self.visit(child,c0,c1) {
+                return Some(leaf);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{Octree, raycast2};
+
+    // the first solid cell on the ray found by stepping through the cells one at a time, with the
+    // t at which the ray enters it
+    fn voxel_walk(tree: &Octree<u8>, start: [f32; 3], dir: [f32; 3]) -> Option<([i32; 3],f32)> {
+        let head = tree.head();
+        let (lo,hi) = (head.position,head.position.map(|p| p + head.size));
+        let mut t = 0_f32;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..3 {
+            let (a,b) = ((lo[axis] as f32 - start[axis]) / dir[axis],(hi[axis] as f32 - start[axis]) / dir[axis]);
+            t = t.max(a.min(b));
+            t_exit = t_exit.min(a.max(b));
+        }
+        if t >= t_exit {
+            return None;
+        }
+        // the cell the ray is in just after entering the root
+        let mut cell = [0; 3];
+        let mut t_next = [0.; 3];
+        let mut step = [0; 3];
+        for axis in 0..3 {
+            let p = start[axis] + dir[axis] * t;
+            cell[axis] = (p.floor() as i32).clamp(lo[axis],hi[axis] - 1);
+            step[axis] = if dir[axis] > 0. { 1 } else { -1 };
+            let edge = if dir[axis] > 0. { cell[axis] + 1 } else { cell[axis] };
+            t_next[axis] = (edge as f32 - start[axis]) / dir[axis];
+        }
+        while (0..3).all(|axis| cell[axis] >= lo[axis] && cell[axis] < hi[axis]) {
+            if tree.is_solid_at(cell) {
+                return Some((cell,t));
+            }
+            let axis = (0..3).min_by(|a,b| t_next[*a].total_cmp(&t_next[*b])).unwrap();
+            t = t_next[axis];
+            cell[axis] += step[axis];
+            t_next[axis] += 1. / dir[axis].abs();
+        }
+        None
+    }
+
+    #[test]
+    fn octree_raycast_matches_a_voxel_walk() {
+        let mut seed = 0x2545F491_u32;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut tree: Octree<u8> = SpatialTree::new(16,[0,0,0]);
+        // single cells and whole blocks of 4 that merge into one leaf
+        for _ in 0..40 {
+            tree.set([(rand() % 16) as i32,(rand() % 16) as i32,(rand() % 16) as i32],1);
+        }
+        for _ in 0..4 {
+            let corner = [(rand() % 4) as i32 * 4,(rand() % 4) as i32 * 4,(rand() % 4) as i32 * 4];
+            for i in 0..64 {
+                tree.set([corner[0] + i % 4,corner[1] + i / 4 % 4,corner[2] + i / 16],2);
+            }
+        }
+        assert!(tree.leaves().any(|leaf| leaf.is_full() && leaf.size == 4));
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let mut coord = || (rand() % 2400) as f32 / 100. - 4.;
+            let start = [coord(),coord(),coord()];
+            let mut component = || (rand() % 2000) as f32 / 1000. - 1.;
+            let dir = [component(),component(),component()];
+            if dir.iter().any(|d| d.abs() < 1e-2) {
+                continue;
+            }
+            let hit = raycast2(Vec3::new(start[0],start[1],start[2]),Vec3::new(dir[0],dir[1],dir[2]),&tree);
+            match (voxel_walk(&tree,start,dir),hit) {
+                (None,None) => (),
+                (Some((cell,t)),Some(hit)) => {
+                    hits += 1;
+                    assert!(hit.node.contains(cell),"start {:?} dir {:?}",start,dir);
+                    assert!((hit.t_enter - t).abs() < 1e-3,"start {:?} dir {:?}",start,dir);
+                    // the normal points back against the ray on the entered face
+                    let normal = [hit.normal.x,hit.normal.y,hit.normal.z];
+                    let axis = normal.iter().position(|n| *n != 0.).unwrap();
+                    assert!(normal[axis] * dir[axis] < 0.);
+                }
+                (walk,hit) => panic!("the walk hit {:?} and the octree {:?}, start {:?} dir {:?}",
+                    walk.map(|w| w.0),hit.map(|h| h.node.position),start,dir),
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn shrinks_back_to_the_original_root() {
+        let mut tree: SpatialTree<u8, 2> = SpatialTree::new(16,[0,0]);
+        tree.set_growable(true);
+        tree.set([-5,-5],1);
+        assert!(tree.head().size > 16);
+        tree.remove_block([-5,-5]);
+        assert_eq!((tree.head().size,tree.head().position),(16,[0,0]));
+
+        // a cell kept outside of the original root stops the shrinking before it's cut off
+        tree.set([3,3],1);
+        tree.set([-5,-5],2);
+        tree.remove_block([3,3]);
+        assert_eq!(tree.get([-5,-5]),Some(&2));
+        assert!(tree.head().contains([0,0]) && tree.head().contains([15,15]));
+        tree.remove_block([-5,-5]);
+        assert_eq!((tree.head().size,tree.head().position),(16,[0,0]));
+    }
+}
@@ -0,0 +1,481 @@
+#![allow(dead_code)]
+use std::collections::HashSet;
+use std::f32::consts::TAU;
+use my_math::prelude::*;
+use crate::quadtree::*;
+use crate::raycast::*;
+
+// the angle on both sides of a corner at which the rays slip past it
+const CORNER_OFFSET: f32 = 1e-4;
+// the rays cast all around so the polygon follows the max radius where nothing blocks the view
+const CIRCLE_RAYS: usize = 64;
+// how far into a cell the light must get for the cell to be in it, in cells
+const SLOPE_MARGIN: f32 = 1e-4;
+
+// the octants of the shadowcasting as the steps in the world of a row (depth) and of a column
+// along the row
+const OCTANTS: [((i32,i32),(i32,i32)); 8] = [
+    ((1,0),(0,1)), ((1,0),(0,-1)), ((-1,0),(0,1)), ((-1,0),(0,-1)),
+    ((0,1),(1,0)), ((0,1),(-1,0)), ((0,-1),(1,0)), ((0,-1),(-1,0)),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FovMode {
+    // every cell in the light is visible, a floor can see a wall that can't see it back
+    Standard,
+    // a floor is only visible if the line to its center is in the light so the floors that
+    // see each other do it both ways
+    Symmetric,
+    // a cell is visible if any part of it is in the light and the walls only cast the shadow
+    // they cast over their whole depth, the view leaks through diagonal gaps and around corners
+    Permissive,
+}
+
+// the cells seen from a cell by the shadowcasting, the empty leaves that are seen whole are kept
+// by their id instead of cell by cell so it's only valid until the tree is changed
+pub struct VisibilitySet {
+    pub origin: IVec2,
+    pub radius: i32,
+    // the visible cells outside of the whole leaves
+    pub cells: HashSet<(i32,i32)>,
+    pub leaves: HashSet<u32>,
+}
+impl VisibilitySet {
+    pub fn is_visible<T: PartialEq + Clone>(&self, tree: &Quadtree<T>, cell: IVec2) -> bool {
+        self.cells.contains(&(cell.x,cell.y)) || tree.leaf_at(cell).is_some_and(|leaf| self.leaves.contains(&leaf))
+    }
+    // the number of visible cells
+    pub fn len<T>(&self, tree: &Quadtree<T>) -> usize {
+        self.cells.len() + self.leaves.iter().map(|leaf| (tree.nodes[*leaf as usize].size as usize).pow(2)).sum::<usize>()
+    }
+    // every visible cell, with the cells of the whole leaves
+    pub fn to_cells<T>(&self, tree: &Quadtree<T>) -> HashSet<(i32,i32)> {
+        let mut cells = self.cells.clone();
+        for leaf in &self.leaves {
+            let node = &tree.nodes[*leaf as usize];
+            let pos = node.pos();
+            for y in pos.y..pos.y + node.size {
+                cells.extend((pos.x..pos.x + node.size).map(|x| (x,y)));
+            }
+        }
+        cells
+    }
+}
+
+// the range of depths and of columns a square of the world covers in the octant
+fn octant_bounds(origin: IVec2, pos: [i32; 2], size: i32, depth_step: (i32,i32), col_step: (i32,i32)) -> ((i32,i32),(i32,i32)) {
+    let along = |step: (i32,i32)| {
+        let a = (pos[0] - origin.x) * step.0 + (pos[1] - origin.y) * step.1;
+        let b = (pos[0] + size - 1 - origin.x) * step.0 + (pos[1] + size - 1 - origin.y) * step.1;
+        (a.min(b),a.max(b))
+    };
+    (along(depth_step),along(col_step))
+}
+
+// the slope at which the light starts again after a wall ending before the column
+fn start_slope(col: i32, depth: i32, mode: FovMode) -> f32 {
+    let edge = col as f32 - 0.5;
+    match mode {
+        // the lowest slope of the wall's edge over its depth
+        FovMode::Permissive => edge / (depth as f32 + 0.5),
+        _ => edge / depth as f32,
+    }
+}
+
+// the slope at which the light ends before a wall starting at the column
+fn end_slope(col: i32, depth: i32, mode: FovMode) -> f32 {
+    let edge = col as f32 - 0.5;
+    match mode {
+        // the highest slope of the wall's edge over its depth
+        FovMode::Permissive => edge / (depth as f32 + if edge > 0. { -0.5 } else { 0.5 }),
+        _ => edge / depth as f32,
+    }
+}
+
+// the columns of the row in the light between the slopes
+// a cell the light only touches at its edge isn't in it, the margin keeps the rounding of the
+// slopes from letting it in
+fn row_cols(depth: i32, start: f32, end: f32, mode: FovMode) -> (i32,i32) {
+    let (min_col,max_col) = match mode {
+        // any part of the cell in the light, the light is the widest at one end of the cell
+        FovMode::Permissive => {
+            let lo = start * (depth as f32 + if start < 0. { 0.5 } else { -0.5 });
+            let hi = end * (depth as f32 + if end > 0. { 0.5 } else { -0.5 });
+            ((lo - 0.5 + SLOPE_MARGIN).floor() as i32 + 1, (hi + 0.5 - SLOPE_MARGIN).ceil() as i32 - 1)
+        }
+        // the line through the middle of the row
+        _ => ((depth as f32 * start + 0.5 + SLOPE_MARGIN).floor() as i32, (depth as f32 * end - 0.5 - SLOPE_MARGIN).ceil() as i32),
+    };
+    (min_col.max(0),max_col.min(depth))
+}
+
+impl<T: PartialEq + Clone> Quadtree<T> {
+    // true if one of the four cells around the grid point is empty, the corners inside of a solid
+    // area can't be seen
+    fn is_open_corner(&self, corner: IVec2) -> bool {
+        [(0,0),(-1,0),(0,-1),(-1,-1)].iter().any(|(dx,dy)| !self.is_solid_at(ivec2!(corner.x + dx, corner.y + dy)))
+    }
+    // the polygon around the area visible from the eye up to max_radius, ordered by the angle from
+    // the eye so it can be filled as a fan of triangles from the eye
+    // a ray is cast toward every visible corner of the full leaves and slightly to both sides of
+    // it so the polygon follows the walls and the shadows behind the corners
+    pub fn visibility_polygon(&self, eye: Vec2, max_radius: f32) -> Vec<Vec2> {
+        let mut angles: Vec<f32> = (0..CIRCLE_RAYS).map(|i| i as f32 / CIRCLE_RAYS as f32 * TAU).collect();
+
+        let min = ivec2!((eye.x - max_radius).floor() as i32, (eye.y - max_radius).floor() as i32);
+        let max = ivec2!((eye.x + max_radius).ceil() as i32 + 1, (eye.y + max_radius).ceil() as i32 + 1);
+        for node in self.query_aabb(min,max).filter(|node| node.is_full()) {
+            let pos = node.pos();
+            for (dx,dy) in [(0,0),(1,0),(0,1),(1,1)] {
+                let corner = ivec2!(pos.x + dx * node.size, pos.y + dy * node.size);
+                let to_corner = Vec2::new(corner.x as f32 - eye.x, corner.y as f32 - eye.y);
+                if to_corner.x * to_corner.x + to_corner.y * to_corner.y > max_radius * max_radius || !self.is_open_corner(corner) {
+                    continue;
+                }
+                let angle = to_corner.y.atan2(to_corner.x);
+                for offset in [-CORNER_OFFSET, 0., CORNER_OFFSET] {
+                    angles.push((angle + offset).rem_euclid(TAU));
+                }
+            }
+        }
+        angles.sort_by(|a,b| a.total_cmp(b));
+        angles.dedup();
+
+        angles.iter().map(|angle| {
+            let dir = Vec2::new(angle.cos(), angle.sin());
+            match raycast_segment(eye,dir,0.,max_radius,self) {
+                Some(hit) => hit.point,
+                None => eye + dir * max_radius,
+            }
+        }).collect()
+    }
+    // the number of cells from the cell along the step that are in the same leaf and the leaf,
+    // outside of the tree the cells are taken one by one
+    fn leaf_run(&self, cell: IVec2, step: (i32,i32)) -> (i32,Option<u32>) {
+        let Some(leaf) = self.leaf_at(cell) else {
+            return (1,None);
+        };
+        let node = &self.nodes[leaf as usize];
+        let pos = node.pos();
+        let run = match step {
+            (1,_) => pos.x + node.size - cell.x,
+            (-1,_) => cell.x - pos.x + 1,
+            (_,1) => pos.y + node.size - cell.y,
+            _ => cell.y - pos.y + 1,
+        };
+        (run,Some(leaf))
+    }
+    // the cells visible from the origin up to the radius found by recursive shadowcasting in the
+    // eight octants, the rows are read through the leaves of the tree so a run of cells in the
+    // same leaf is handled at once instead of looking up every cell
+    // an empty leaf reached at its first row in the octant is marked whole if all of its cells are
+    // in the light of their rows and no wall is in the light over its rows, then the scan would
+    // get to every one of its cells with the same light
+    pub fn compute_fov(&self, origin: IVec2, radius: i32) -> VisibilitySet {
+        self.compute_fov_with(origin,radius,FovMode::Standard)
+    }
+    pub fn compute_fov_with(&self, origin: IVec2, radius: i32, mode: FovMode) -> VisibilitySet {
+        let mut visible = VisibilitySet { origin, radius, cells: HashSet::new(), leaves: HashSet::new() };
+        visible.cells.insert((origin.x,origin.y));
+        for (depth_step,col_step) in OCTANTS {
+            // the rows still to scan with the slopes of the light in them
+            let mut rows = vec![(1,0_f32,1_f32)];
+            while let Some((depth,mut start,end)) = rows.pop() {
+                if depth > radius || start >= end {
+                    continue;
+                }
+                let (min_col,max_col) = row_cols(depth,start,end,mode);
+                let row = ivec2!(origin.x + depth_step.0 * depth, origin.y + depth_step.1 * depth);
+                let mut prev_solid = None;
+                let mut col = min_col;
+                while col <= max_col {
+                    let cell = ivec2!(row.x + col_step.0 * col, row.y + col_step.1 * col);
+                    let (run,leaf) = self.leaf_run(cell,col_step);
+                    let solid = leaf.is_some_and(|leaf| self.nodes[leaf as usize].is_full());
+                    let last = (col + run - 1).min(max_col);
+                    let whole = match leaf {
+                        Some(leaf) if !solid => visible.leaves.contains(&leaf)
+                            || self.whole_leaf_visible(leaf,&visible,depth,start,end,mode,(depth_step,col_step)),
+                        _ => false,
+                    };
+                    if whole {
+                        visible.leaves.insert(leaf.unwrap());
+                    } else {
+                        for c in col..=last {
+                            let symmetric = c as f32 >= depth as f32 * start && c as f32 <= depth as f32 * end;
+                            if (solid || mode != FovMode::Symmetric || symmetric) && c * c + depth * depth <= radius * radius {
+                                visible.cells.insert((row.x + col_step.0 * c,row.y + col_step.1 * c));
+                            }
+                        }
+                    }
+                    // the permissive slopes of a wall at the edge of the light can reach past it
+                    match (prev_solid,solid) {
+                        // the light starts again after the wall
+                        (Some(true),false) => start = start_slope(col,depth,mode).max(start),
+                        // the wall ends the light of this part of the row
+                        (Some(false),true) => rows.push((depth + 1,start,end_slope(col,depth,mode).min(end))),
+                        _ => {}
+                    }
+                    prev_solid = Some(solid);
+                    col = last + 1;
+                }
+                if prev_solid == Some(false) {
+                    rows.push((depth + 1,start,end));
+                }
+            }
+        }
+        // the cells the other octants added inside of the whole leaves
+        let leaves = &visible.leaves;
+        visible.cells.retain(|(x,y)| !self.leaf_at(ivec2!(*x,*y)).is_some_and(|leaf| leaves.contains(&leaf)));
+        visible
+    }
+    // true if the empty leaf starts at the row and every one of its cells gets the light between
+    // the slopes
+    #[allow(clippy::too_many_arguments)]
+    fn whole_leaf_visible(&self, leaf: u32, visible: &VisibilitySet, depth: i32, start: f32, end: f32, mode: FovMode,
+        (depth_step,col_step): ((i32,i32),(i32,i32))) -> bool {
+        let node = &self.nodes[leaf as usize];
+        let ((near,far),(left,right)) = octant_bounds(visible.origin,node.position,node.size,depth_step,col_step);
+        // the light only gets wider with the depth so the narrowest bounds are the ones that count
+        if near != depth || left < row_cols(far,start,end,mode).0 || right > row_cols(near,start,end,mode).1 {
+            return false;
+        }
+        if mode == FovMode::Symmetric && ((left as f32) < far as f32 * start || right as f32 > near as f32 * end) {
+            return false;
+        }
+        if left.abs().max(right.abs()).pow(2) + far * far > visible.radius * visible.radius {
+            return false;
+        }
+        // a wall in the light beside the leaf could still shadow part of it
+        !self.any_solid_in(|pos,size| {
+            let ((a,b),(c,e)) = octant_bounds(visible.origin,pos,size,depth_step,col_step);
+            if b < near || a > far {
+                return Coverage::Outside;
+            }
+            let (first,last) = (a.max(near),b.min(far));
+            if e < row_cols(first,start,end,mode).0 || c > row_cols(last,start,end,mode).1 {
+                return Coverage::Outside;
+            }
+            if a >= near && b <= far && c >= row_cols(b,start,end,mode).0 && e <= row_cols(a,start,end,mode).1 {
+                Coverage::Inside
+            } else {
+                Coverage::Partial
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the slopes from the origin a cell takes up in an octant, the cells are squares around
+    // (depth,col), the standard and symmetric modes only see them along the middle of their row
+    fn cell_slopes(depth: i32, col: i32, mode: FovMode) -> (f64,f64) {
+        let (depth,col) = (depth as f64,col as f64);
+        match mode {
+            FovMode::Permissive => {
+                let lo = if col > 0. { (col - 0.5) / (depth + 0.5) } else { (col - 0.5) / (depth - 0.5) };
+                (lo,(col + 0.5) / (depth - 0.5))
+            }
+            _ => ((col - 0.5) / depth,(col + 0.5) / depth),
+        }
+    }
+
+    // the slopes a run of walls from first to last along a row shadows, in the permissive mode
+    // only the rays crossing it over its whole depth are blocked
+    fn wall_slopes(depth: i32, first: i32, last: i32, mode: FovMode) -> (f64,f64) {
+        let (depth,lo,hi) = (depth as f64,first as f64 - 0.5,last as f64 + 0.5);
+        match mode {
+            FovMode::Permissive => {
+                let lo = if lo > 0. { lo / (depth - 0.5) } else { lo / (depth + 0.5) };
+                (lo,hi / (depth + 0.5))
+            }
+            _ => (lo / depth,hi / depth),
+        }
+    }
+
+    // true if some of the slopes between lo and hi within the octant aren't shadowed
+    fn lit(shadows: &[(f64,f64)], lo: f64, hi: f64) -> bool {
+        let (lo,hi) = (lo.max(0.),hi.min(1.));
+        if lo >= hi {
+            return false;
+        }
+        let mut covered = lo;
+        for (a,b) in shadows {
+            if *a > covered + 1e-9 {
+                break;
+            }
+            covered = covered.max(*b);
+        }
+        covered < hi - 1e-9
+    }
+
+    // the field of view worked out from the slopes alone, a cell is visible if the light between
+    // the shadows of the walls in the rows before it reaches it, a floor in the symmetric mode if
+    // the light reaches the slope of its center
+    fn reference_fov(tree: &Quadtree<u8>, origin: IVec2, radius: i32, mode: FovMode) -> HashSet<(i32,i32)> {
+        let mut cells = HashSet::from([(origin.x,origin.y)]);
+        for (depth_step,col_step) in OCTANTS {
+            let world = |depth: i32,col: i32| ivec2!(origin.x + depth_step.0 * depth + col_step.0 * col, origin.y + depth_step.1 * depth + col_step.1 * col);
+            let mut shadows = Vec::new();
+            for depth in 1..=radius {
+                shadows.sort_by(|a: &(f64,f64),b| a.0.total_cmp(&b.0));
+                for col in 0..=depth {
+                    let cell = world(depth,col);
+                    let solid = tree.is_solid_at(cell);
+                    let (lo,hi) = cell_slopes(depth,col,mode);
+                    let visible = if mode == FovMode::Symmetric && !solid {
+                        let center = col as f64 / depth as f64;
+                        lit(&shadows,center - 1e-6,center) || lit(&shadows,center,center + 1e-6)
+                    } else {
+                        lit(&shadows,lo,hi)
+                    };
+                    if visible && col * col + depth * depth <= radius * radius {
+                        cells.insert((cell.x,cell.y));
+                    }
+                }
+                // the walls of the row only shadow the rows after it
+                let mut run = None;
+                for col in 0..=depth + 1 {
+                    let solid = col <= depth && tree.is_solid_at(world(depth,col));
+                    match (run,solid) {
+                        (None,true) => run = Some(col),
+                        (Some(first),false) => {
+                            shadows.push(wall_slopes(depth,first,col - 1,mode));
+                            run = None;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    fn test_tree(seed: &mut u32) -> Quadtree<u8> {
+        let mut rand = move || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 17;
+            *seed ^= *seed << 5;
+            *seed
+        };
+        let mut tree = Quadtree::new(64,ivec2!(0,0));
+        for _ in 0..12 {
+            let (x,y) = ((rand() % 60) as i32,(rand() % 60) as i32);
+            tree.fill_rect(ivec2!(x,y),ivec2!(x + 1 + (rand() % 4) as i32,y + 1 + (rand() % 4) as i32),1);
+        }
+        tree
+    }
+
+    #[test]
+    fn single_wall_by_hand() {
+        let mut tree: Quadtree<u8> = Quadtree::new(16,ivec2!(0,0));
+        tree.set(ivec2!(6,4),1);
+        let disc = |hidden: &[(i32,i32)]| {
+            let mut cells = HashSet::new();
+            for y in -2..=10 {
+                for x in -2..=10 {
+                    if (x - 4) * (x - 4) + (y - 4) * (y - 4) <= 36 && !hidden.contains(&(x,y)) {
+                        cells.insert((x,y));
+                    }
+                }
+            }
+            cells
+        };
+        // the row straight behind the wall is in its shadow
+        let behind = [(7,4),(8,4),(9,4),(10,4)];
+        assert_eq!(tree.compute_fov_with(ivec2!(4,4),6,FovMode::Standard).to_cells(&tree),disc(&behind));
+        assert_eq!(tree.compute_fov_with(ivec2!(4,4),6,FovMode::Permissive).to_cells(&tree),disc(&behind));
+        // the centers of (9,3) and (9,5) are a fifth of a cell off the axis, within the quarter
+        // the wall covers at its middle
+        let mut symmetric = behind.to_vec();
+        symmetric.extend([(9,3),(9,5)]);
+        assert_eq!(tree.compute_fov_with(ivec2!(4,4),6,FovMode::Symmetric).to_cells(&tree),disc(&symmetric));
+    }
+
+    #[test]
+    fn matches_the_slope_reference() {
+        let mut seed = 0x9E3779B9;
+        for _ in 0..100 {
+            let tree = test_tree(&mut seed);
+            let origin = ivec2!((seed % 64) as i32,((seed >> 8) % 64) as i32);
+            for mode in [FovMode::Standard,FovMode::Symmetric,FovMode::Permissive] {
+                let fov = tree.compute_fov_with(origin,24,mode);
+                let cells = fov.to_cells(&tree);
+                let reference = reference_fov(&tree,origin,24,mode);
+                let mut only_fov: Vec<_> = cells.difference(&reference).collect();
+                let mut only_reference: Vec<_> = reference.difference(&cells).collect();
+                only_fov.sort();
+                only_reference.sort();
+                assert!(only_fov.is_empty() && only_reference.is_empty(),"{:?} from {:?}, only seen by the scan {:?}, only by the reference {:?}",
+                    mode,(origin.x,origin.y),only_fov,only_reference);
+                assert_eq!(fov.len(&tree),cells.len());
+            }
+        }
+    }
+
+    #[test]
+    fn open_leaves_are_marked_whole() {
+        let mut tree: Quadtree<u8> = Quadtree::new(64,ivec2!(0,0));
+        tree.set(ivec2!(20,20),1);
+        // the leaf from (16,16) to (20,20) is in the light of a single octant
+        let fov = tree.compute_fov(ivec2!(8,20),20);
+        let leaf = tree.leaf_at(ivec2!(16,16)).unwrap();
+        assert_eq!(tree.nodes[leaf as usize].size,4);
+        assert!(fov.leaves.contains(&leaf));
+        assert!(!fov.cells.contains(&(17,17)) && fov.is_visible(&tree,ivec2!(17,17)));
+        // behind the wall
+        assert!(!fov.is_visible(&tree,ivec2!(21,20)));
+    }
+
+    #[test]
+    fn polygon_vertices_are_seen_from_the_eye() {
+        let mut seed = 0xC0FFEE;
+        for _ in 0..10 {
+            let tree = test_tree(&mut seed);
+            let eye = Vec2::new((seed % 6400) as f32 / 100.,((seed >> 12) % 6400) as f32 / 100.);
+            if tree.is_solid_at(ivec2!(eye.x.floor() as i32,eye.y.floor() as i32)) {
+                continue;
+            }
+            let polygon = tree.visibility_polygon(eye,20.);
+            let mut prev_angle = f32::NEG_INFINITY;
+            for vertex in polygon {
+                let to_vertex = vertex - eye;
+                let distance = (to_vertex.x * to_vertex.x + to_vertex.y * to_vertex.y).sqrt();
+                assert!(distance <= 20. + 1e-3);
+                let angle = to_vertex.y.atan2(to_vertex.x).rem_euclid(TAU);
+                assert!(angle >= prev_angle - 1e-3, "the vertices are ordered by angle");
+                prev_angle = angle;
+                // the vertex lies on a wall or at the radius, just short of it the eye sees it
+                // a ray through a corner only touches it so the line to the vertex can graze it
+                let before = eye + to_vertex * (1. - 1e-3 / distance.max(1e-3));
+                let grazes = |hit: RayHit<u8>| {
+                    let (pos,size) = (hit.node.pos(),hit.node.size as f32);
+                    let near = |p: f32,lo: i32| (p - lo as f32).abs() < 1e-3 || (p - lo as f32 - size).abs() < 1e-3;
+                    near(hit.point.x,pos.x) && near(hit.point.y,pos.y)
+                };
+                assert!(raycast_segment(eye,before - eye,0.,1.,&tree).is_none_or(grazes),
+                    "eye ({},{}) vertex ({},{})",eye.x,eye.y,vertex.x,vertex.y);
+                let cell = |dx: f32,dy: f32| ivec2!((vertex.x + dx).floor() as i32,(vertex.y + dy).floor() as i32);
+                let around = [cell(-1e-3,-1e-3),cell(1e-3,-1e-3),cell(-1e-3,1e-3),cell(1e-3,1e-3)];
+                let on_wall = around.iter().any(|cell| tree.is_solid_at(*cell));
+                let on_border = around.iter().any(|cell| tree.leaf_at(*cell).is_none());
+                assert!(on_wall || on_border || distance > 20. - 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_floors_see_each_other() {
+        let mut seed = 0x12345;
+        let tree = test_tree(&mut seed);
+        let origin = ivec2!(30,30);
+        let fov = tree.compute_fov_with(origin,16,FovMode::Symmetric);
+        for (x,y) in fov.to_cells(&tree) {
+            if !tree.is_solid_at(ivec2!(x,y)) {
+                assert!(tree.compute_fov_with(ivec2!(x,y),16,FovMode::Symmetric).is_visible(&tree,origin));
+            }
+        }
+    }
+}